@@ -44,6 +44,12 @@ enum DisplayType {
     RenameAllSnake,
     RenameAllKebab,
     RenameAllCamel,
+    MaxItems(usize),
+    Truncate(usize),
+    With(TokenStream2),
+    Debug,
+    Display,
+    DebugLeaf,
 }
 
 #[derive(Debug, Clone)]
@@ -70,6 +76,24 @@ impl SkipType {
     }
 }
 
+#[derive(Debug, Clone)]
+enum FieldFormat {
+    With(TokenStream2),
+    Debug,
+    Display,
+}
+
+impl FieldFormat {
+    fn from_display_type(display_type: &DisplayType) -> Option<FieldFormat> {
+        match display_type {
+            DisplayType::With(path) => Some(FieldFormat::With(path.clone())),
+            DisplayType::Debug => Some(FieldFormat::Debug),
+            DisplayType::Display => Some(FieldFormat::Display),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 enum RenameType {
     Str(String),
@@ -109,6 +133,10 @@ trait VecExt {
     fn try_get_tag(&self) -> Result<Option<TagType>>;
     fn try_get_transparent(&self) -> Result<Option<()>>;
     fn try_get_skip(&self) -> Result<Option<SkipType>>;
+    fn try_get_max_items(&self) -> Result<Option<usize>>;
+    fn try_get_truncate(&self) -> Result<Option<usize>>;
+    fn try_get_format(&self) -> Result<Option<FieldFormat>>;
+    fn try_get_debug_leaf(&self) -> Result<Option<()>>;
 }
 
 impl VecExt for Vec<DisplayType> {
@@ -231,6 +259,63 @@ impl VecExt for Vec<DisplayType> {
         }
         Ok(transparent)
     }
+
+    fn try_get_max_items(&self) -> Result<Option<usize>> {
+        let mut iter = self.iter().filter_map(|d| match d {
+            DisplayType::MaxItems(max_items) => Some(*max_items),
+            _ => None,
+        });
+        let max_items = iter.next();
+        if iter.next().is_some() {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "Only one max_items attribute is allowed",
+            ));
+        }
+        Ok(max_items)
+    }
+
+    fn try_get_truncate(&self) -> Result<Option<usize>> {
+        let mut iter = self.iter().filter_map(|d| match d {
+            DisplayType::Truncate(truncate) => Some(*truncate),
+            _ => None,
+        });
+        let truncate = iter.next();
+        if iter.next().is_some() {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "Only one truncate attribute is allowed",
+            ));
+        }
+        Ok(truncate)
+    }
+
+    fn try_get_format(&self) -> Result<Option<FieldFormat>> {
+        let mut iter = self.iter().filter_map(FieldFormat::from_display_type);
+        let format = iter.next();
+        if iter.next().is_some() {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "Only one of with, debug and display is allowed",
+            ));
+        }
+        Ok(format)
+    }
+
+    fn try_get_debug_leaf(&self) -> Result<Option<()>> {
+        let mut iter = self
+            .iter()
+            .filter(|&d| matches!(d, &DisplayType::DebugLeaf))
+            .map(|_| ());
+        let debug_leaf = iter.next();
+        if iter.next().is_some() {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "Only one debug_leaf attribute is allowed",
+            ));
+        }
+        Ok(debug_leaf)
+    }
 }
 
 fn spanned_tokens(s: &syn::LitStr) -> parse::Result<TokenStream2> {
@@ -280,6 +365,8 @@ fn parse_attributes(attrs: &[syn::Attribute]) -> Result<Vec<DisplayType>> {
                                 Some(DisplayType::Flatten)
                             } else if path.is_ident("transparent") {
                                 Some(DisplayType::Transparent)
+                            } else if path.is_ident("untagged") {
+                                Some(DisplayType::Untagged)
                             } else if path.is_ident("tag") {
                                 return Some(Err(syn::Error::new(Span::call_site(), "tag requires a string literal as an argument")));
                             } else if path.is_ident("content") {
@@ -298,6 +385,18 @@ fn parse_attributes(attrs: &[syn::Attribute]) -> Result<Vec<DisplayType>> {
                                 Some(DisplayType::SkipIfEmpty)
                             } else if path.is_ident("rename") {
                                 return Some(Err(syn::Error::new(Span::call_site(), "rename requires a string literal as an argument")));
+                            } else if path.is_ident("debug") {
+                                Some(DisplayType::Debug)
+                            } else if path.is_ident("display") {
+                                Some(DisplayType::Display)
+                            } else if path.is_ident("debug_leaf") {
+                                Some(DisplayType::DebugLeaf)
+                            } else if path.is_ident("with") {
+                                return Some(Err(syn::Error::new(Span::call_site(), "with requires a string literal as an argument, that contains a function path")));
+                            } else if path.is_ident("max_items") {
+                                return Some(Err(syn::Error::new(Span::call_site(), "max_items requires an integer literal as an argument")));
+                            } else if path.is_ident("truncate") {
+                                return Some(Err(syn::Error::new(Span::call_site(), "truncate requires an integer literal as an argument")));
                             } else if path.is_ident("rename_pascal") {
                                 Some(DisplayType::RenamePascal)
                             } else if path.is_ident("rename_snake") {
@@ -330,6 +429,25 @@ fn parse_attributes(attrs: &[syn::Attribute]) -> Result<Vec<DisplayType>> {
                                     let tokens = spanned_tokens(lit_str).expect("Failed to parse tokens for skip_if");
                                     let parsed = syn::parse2(tokens).expect("Failed to parse skip_if");
                                     Some(DisplayType::SkipIf(parsed))
+                                } else if name.path.is_ident("with") {
+                                    match spanned_tokens(lit_str).and_then(syn::parse2::<syn::Path>) {
+                                        Ok(path) => Some(DisplayType::With(quote! { #path })),
+                                        Err(e) => return Some(Err(e)),
+                                    }
+                                } else {
+                                    None
+                                }
+                            } else if let syn::Lit::Int(lit_int) = &name.lit {
+                                if name.path.is_ident("max_items") {
+                                    match lit_int.base10_parse() {
+                                        Ok(max_items) => Some(DisplayType::MaxItems(max_items)),
+                                        Err(e) => return Some(Err(e)),
+                                    }
+                                } else if name.path.is_ident("truncate") {
+                                    match lit_int.base10_parse() {
+                                        Ok(truncate) => Some(DisplayType::Truncate(truncate)),
+                                        Err(e) => return Some(Err(e)),
+                                    }
                                 } else {
                                     None
                                 }
@@ -356,6 +474,9 @@ struct FieldAttributes {
     flatten: bool,
     skip: Option<SkipType>,
     rename: Option<RenameType>,
+    max_items: Option<usize>,
+    truncate: Option<usize>,
+    format: Option<FieldFormat>,
 }
 
 #[derive(Debug, Clone)]
@@ -370,6 +491,7 @@ enum TagType {
 #[derive(Debug, Clone)]
 struct ContainerAttributes {
     transparent: bool,
+    debug_leaf: bool,
     tag: Option<TagType>,
     rename_all: Option<RenameType>,
 }
@@ -398,10 +520,29 @@ fn parse_field_attributes(attrs: &[syn::Attribute]) -> Result<FieldAttributes> {
         ));
     }
 
+    let flatten = parsed_attrs.try_get_flatten()?.is_some();
+    let format = parsed_attrs.try_get_format()?;
+    if flatten && format.is_some() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "Cannot use flatten with any of with, debug and display",
+        ));
+    }
+
+    if parsed_attrs.try_get_debug_leaf()?.is_some() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "debug_leaf is not supported for fields",
+        ));
+    }
+
     Ok(FieldAttributes {
-        flatten: parsed_attrs.try_get_flatten()?.is_some(),
+        flatten,
         skip: parsed_attrs.try_get_skip()?,
         rename: parsed_attrs.try_get_rename()?,
+        max_items: parsed_attrs.try_get_max_items()?,
+        truncate: parsed_attrs.try_get_truncate()?,
+        format,
     })
 }
 
@@ -415,8 +556,30 @@ fn parse_container_attributes(attrs: &[syn::Attribute]) -> Result<ContainerAttri
         ));
     }
 
+    if parsed_attrs.try_get_max_items()?.is_some() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "max_items is not supported for containers",
+        ));
+    }
+
+    if parsed_attrs.try_get_truncate()?.is_some() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "truncate is not supported for containers",
+        ));
+    }
+
+    if parsed_attrs.try_get_format()?.is_some() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "with, debug and display are not supported for containers",
+        ));
+    }
+
     Ok(ContainerAttributes {
         transparent: parsed_attrs.try_get_transparent()?.is_some(),
+        debug_leaf: parsed_attrs.try_get_debug_leaf()?.is_some(),
         tag: parsed_attrs.try_get_tag()?,
         rename_all: parsed_attrs.try_get_rename_all()?,
     })
@@ -439,6 +602,34 @@ fn parse_variant_attributes(attrs: &[syn::Attribute]) -> Result<VariantAttribute
         ));
     }
 
+    if parsed_attrs.try_get_max_items()?.is_some() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "max_items is not supported for variants",
+        ));
+    }
+
+    if parsed_attrs.try_get_truncate()?.is_some() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "truncate is not supported for variants",
+        ));
+    }
+
+    if parsed_attrs.try_get_format()?.is_some() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "with, debug and display are not supported for variants",
+        ));
+    }
+
+    if parsed_attrs.try_get_debug_leaf()?.is_some() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "debug_leaf is not supported for variants",
+        ));
+    }
+
     if parsed_attrs.try_get_tag()?.is_some() {
         return Err(syn::Error::new(
             Span::call_site(),
@@ -446,8 +637,17 @@ fn parse_variant_attributes(attrs: &[syn::Attribute]) -> Result<VariantAttribute
         ));
     }
 
+    let skip = parsed_attrs.try_get_skip()?;
+    if let Some(SkipType::IfEmpty | SkipType::IfFalse | SkipType::IfTrue | SkipType::IfNone) = skip
+    {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "only skip and skip_if are supported for variants",
+        ));
+    }
+
     Ok(VariantAttributes {
-        skip: parsed_attrs.try_get_skip()?,
+        skip,
         rename: parsed_attrs.try_get_rename()?,
         rename_all: parsed_attrs.try_get_rename_all()?,
     })
@@ -458,13 +658,26 @@ fn parse_variant_attributes(attrs: &[syn::Attribute]) -> Result<VariantAttribute
 // TODO: fn parse_variant_attributes(attrs: &[syn::Attribute]) -> Result<DisplayAttrs>
 // TODO: specific type for each?
 
-fn gen_named_fields(fields: FieldsNamed, rename_all: Option<RenameType>) -> Result<TokenStream2> {
-    let field_render_tuple_code = fields.named.iter().map(|_| {
-        quote! {
-            true,
-        }
-    });
+// Short name of a field type for fields rendered by `with`, `debug` or `display`,
+// which need not implement `TreeDisplay` and so have no `type_name_fmt`
+fn type_label(ty: &Type) -> String {
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string())
+            .unwrap_or_default(),
+        Type::Reference(reference) => type_label(&reference.elem),
+        Type::Paren(paren) => type_label(&paren.elem),
+        Type::Group(group) => type_label(&group.elem),
+        Type::Array(_) | Type::Slice(_) => "Array".to_string(),
+        Type::Tuple(_) => "Tuple".to_string(),
+        _ => quote! { #ty }.to_string(),
+    }
+}
 
+fn gen_named_fields(fields: FieldsNamed, rename_all: Option<RenameType>) -> Result<TokenStream2> {
     let mut fields_with_attrs = fields
         .named
         .iter()
@@ -473,130 +686,369 @@ fn gen_named_fields(fields: FieldsNamed, rename_all: Option<RenameType>) -> Resu
 
     fields_with_attrs.retain(|(_, attrs)| !matches!(attrs.skip, Some(SkipType::Always)));
 
-    let fields_code = fields_with_attrs.into_iter().enumerate().map(move |(i, (field, attrs))| {
+    let fields_code = fields_with_attrs.into_iter().map(move |(field, attrs)| {
         let field_name = field.ident.as_ref().ok_or_else(|| syn::Error::new(Span::call_site(), "Fields must have a name"))?;
         let name_span = field_name.span();
         let field_name_string = attrs.rename.as_ref().map_or(rename_all.as_ref(),  Some).map(|rename_type| rename_named_field(field_name.to_string(), rename_type)).unwrap_or_else(|| field_name.to_string());
         let field_name_stringified = LitStr::new(&field_name_string, name_span);
-        let to_render_index = syn::Index::from(i);
-        let skip_code = if let Some(skip) = attrs.skip {
-            let condition = if let SkipType::If(skip_if) = skip {
-                quote! {
-                    if #skip_if (#field_name) {
-                        to_render. #to_render_index = false;
-                    } else {
-                        last_field = #i;
-                    }
-                }
-            } else if let SkipType::IfFalse = skip {
-                quote! {
-                    !(#field_name)
-                }
-             } else if let SkipType::IfTrue = skip {
-                quote! {
-                    (#field_name)
-                }
-            } else if let SkipType::IfNone = skip {
-                quote! {
-                    (#field_name).is_none()
-                }
-            } else if let SkipType::IfEmpty = skip {
-                quote! {
-                    (#field_name).is_empty()
-                }
-            } else {
-                unreachable!()
-            };
+        let skip_condition = match attrs.skip {
+            Some(SkipType::If(skip_if)) => Some(quote! { #skip_if (#field_name) }),
+            Some(SkipType::IfFalse) => Some(quote! { !*(#field_name) }),
+            Some(SkipType::IfTrue) => Some(quote! { *(#field_name) }),
+            Some(SkipType::IfNone) => Some(quote! { (#field_name).is_none() }),
+            Some(SkipType::IfEmpty) => Some(quote! { (#field_name).is_empty() }),
+            Some(SkipType::Always) => unreachable!(),
+            None => None,
+        };
+        let skip_condition = match (skip_condition, attrs.flatten) {
+            (Some(condition), true) => Some(quote! { #condition || tree_display::TreeDisplay::is_flatten_empty(#field_name) }),
+            (None, true) => Some(quote! { tree_display::TreeDisplay::is_flatten_empty(#field_name) }),
+            (condition, false) => condition,
+        };
+        let max_items_code = attrs.max_items.map(|max_items| quote! { max_items: Some(#max_items), });
+        let truncate_code = attrs.truncate.map(|truncate| quote! { max_leaf_width: Some(#truncate), });
+        let render_code = if attrs.flatten {
             quote! {
-                if #condition {
-                    to_render. #to_render_index = false;
-                } else {
-                    last_field = #i;
-                }
+                let ctx = tree_display::Context { #max_items_code #truncate_code ..ctx };
+                tree_display::TreeDisplay::to_tree_node(#field_name, ctx).flatten_into(&mut __children);
+            }
+        } else if let Some(format) = attrs.format {
+            let node_code = gen_format_node(format, quote! { #field_name }, &field.ty, &field_name_stringified, quote! { #max_items_code #truncate_code });
+            quote! {
+                __children.push(#node_code);
             }
         } else {
             quote! {
-                last_field = #i;
+                let ctx = tree_display::Context { #max_items_code #truncate_code ..ctx };
+                __children.push(tree_display::TreeNode::child(#field_name, #field_name_stringified, tree_display::NodeKind::Field, ctx));
             }
         };
-        // TODO: do flatten at compile time, not at runtime
-        let flatten = attrs.flatten;
-        let render_code = quote! {
-            let mut indent_modified = ctx.indent.to_string();
-            if to_render. #to_render_index {
-                if (! #flatten && last_field > #i) || tctx.is_flattened_and_last == Some(false) {
-                    indent_modified.push_str("|  ");
-                    write!(f, "{}├──{}", ctx.indent, #field_name_stringified)?;
-                } else if ! #flatten {
-                    indent_modified.push_str("   ");
-                    write!(f, "{}└──{}", ctx.indent, #field_name_stringified)?;
-                }
-                if ! #flatten && ctx.show_types {
-                    #field_name.type_name_fmt(f)?;
+        Ok(match skip_condition {
+            Some(condition) => quote! {
+                if !(#condition) {
+                    #render_code
                 }
-                if ! #flatten {
-                    writeln!(f)?;
+            },
+            None => quote! {
+                {
+                    #render_code
                 }
-
-                let mut tctx = tree_display::TransientContext { ..Default::default() };
-                if #flatten {
-                    tctx.is_flattened_and_last = Some(last_field <= #i);
-                }
-                tree_display::TreeDisplay::tree_fmt(&#field_name, f, tree_display::Context { indent: &indent_modified, ..ctx }, tctx)?;
-            }
-        };
-        Ok((skip_code, render_code))
+            },
+        })
     }).collect::<Result<Vec<_>>>()?;
-    let (skipping, rendering): (Vec<_>, Vec<_>) = fields_code.into_iter().unzip();
     Ok(quote! {
-        let mut last_field: usize = 0;
-        let mut to_render = ( #(#field_render_tuple_code)* );
-        #(#skipping)*
-        #(#rendering)*
+        #(#fields_code)*
     })
 }
 
-fn gen_unnamed_fields(fields: FieldsUnnamed) -> impl Iterator<Item = TokenStream2> {
-    let field_count = fields.unnamed.len();
-    fields
+// Leaf of a value rendered by `with`, `debug` or `display`, `field_ref` being a reference
+// to the value and `ctx_code` the context fields set for it
+fn gen_format_leaf(
+    format: FieldFormat,
+    field_ref: TokenStream2,
+    ctx_code: TokenStream2,
+) -> TokenStream2 {
+    let field_ctx = quote! {
+        tree_display::Context { #ctx_code ..ctx }
+    };
+    match format {
+        FieldFormat::With(with) => quote! {
+            tree_display::with_leaf_node(#field_ctx, &|f, ctx| #with(#field_ref, f, ctx, Default::default()))
+        },
+        FieldFormat::Debug => quote! { tree_display::debug_leaf_node(#field_ref, #field_ctx) },
+        FieldFormat::Display => quote! { tree_display::display_leaf_node(#field_ref, #field_ctx) },
+    }
+}
+
+// The labelled node of a field rendered by `with`, `debug` or `display`, which need not
+// implement `TreeDisplay`
+fn gen_format_node(
+    format: FieldFormat,
+    field_ref: TokenStream2,
+    ty: &Type,
+    field_label: &LitStr,
+    ctx_code: TokenStream2,
+) -> TokenStream2 {
+    let type_label = LitStr::new(&format!("({})", type_label(ty)), field_label.span());
+    let node_code = gen_format_leaf(
+        format,
+        field_ref,
+        quote! { rename: Some(#field_label), depth: ctx.depth + 1, #ctx_code },
+    );
+    quote! {
+        tree_display::TreeNode {
+            label: Some(#field_label.to_string()),
+            type_name: Some(#type_label.to_string()),
+            kind: tree_display::NodeKind::Field,
+            ..#node_code
+        }
+    }
+}
+
+fn gen_unnamed_fields(
+    fields: &FieldsUnnamed,
+    accessors: Vec<TokenStream2>,
+) -> Result<TokenStream2> {
+    let fields_code = fields
         .unnamed
-        .into_iter()
+        .iter()
+        .zip(accessors)
         .enumerate()
-        .map(move |(i, _)| {
-            let field_accessor = syn::Index::from(i);
-            if field_count - 1 > i {
-                quote! {
-                    let mut indent_modified = ctx.indent.to_string();
-                    indent_modified.push_str("|  ");
-                    write!(f, "{}├──{}", ctx.indent, #field_accessor)?;
-                    if ctx.show_types {
-                        self.#field_accessor.type_name_fmt(f)?;
+        .map(|(i, (field, field_accessor))| {
+            let attrs = parse_field_attributes(&field.attrs)?;
+            if attrs.flatten || attrs.skip.is_some() || attrs.rename.is_some() {
+                return Err(syn::Error::new(
+                    Span::call_site(),
+                    "flatten, skip and rename are not supported for tuple fields",
+                ));
+            }
+            let field_label = LitStr::new(&i.to_string(), Span::call_site());
+            let max_items_code = attrs.max_items.map(|max_items| quote! { max_items: Some(#max_items), });
+            let truncate_code = attrs.truncate.map(|truncate| quote! { max_leaf_width: Some(#truncate), });
+            Ok(match attrs.format {
+                Some(format) => {
+                    let node_code = gen_format_node(format, quote! { &#field_accessor }, &field.ty, &field_label, quote! { #max_items_code #truncate_code });
+                    quote! {
+                        __children.push(#node_code);
                     }
-                    writeln!(f)?;
-                    tree_display::TreeDisplay::tree_fmt(&self.#field_accessor, f, tree_display::Context { indent: &indent_modified, ..ctx }, Default::default())?;
                 }
-            } else {
-                quote! {
-                    let mut indent_modified = ctx.indent.to_string();
-                    indent_modified.push_str("   ");
-                    write!(f, "{}└──{}", ctx.indent, #field_accessor)?;
-                    if ctx.show_types {
-                        self.#field_accessor.type_name_fmt(f)?;
+                None => quote! {
+                    {
+                        let ctx = tree_display::Context { #max_items_code #truncate_code ..ctx };
+                        __children.push(tree_display::TreeNode {
+                            type_name: Some(tree_display::type_name_from(|f| {
+                                use tree_display::TreeDisplay as _;
+                                #field_accessor.type_name_fmt(f)
+                            })),
+                            ..tree_display::TreeNode::child(&#field_accessor, #field_label, tree_display::NodeKind::Field, ctx)
+                        });
+                    }
+                },
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(quote! {
+        #(#fields_code)*
+    })
+}
+
+fn gen_tag_code(tag: &str, variant_name: &LitStr) -> TokenStream2 {
+    let tag_stringified = LitStr::new(tag, Span::call_site());
+    quote! {
+        __children.push(tree_display::TreeNode::child(&#variant_name, #tag_stringified, tree_display::NodeKind::Field, ctx));
+    }
+}
+
+// The accessor of the one field a transparent struct is displayed as, with the field
+// and its attributes
+fn gen_transparent_accessor(data: &Data) -> Result<(TokenStream2, &Field, FieldAttributes)> {
+    let fields = match data {
+        Data::Struct(DataStruct { fields, .. }) => fields,
+        _ => {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "transparent is only supported for structs",
+            ))
+        }
+    };
+
+    let mut rendered_fields = fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| parse_field_attributes(&field.attrs).map(|attrs| (i, field, attrs)))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|(_, _, attrs)| !matches!(attrs.skip, Some(SkipType::Always)))
+        .map(|(i, field, attrs)| match &field.ident {
+            Some(ident) => (quote! { self.#ident }, field, attrs),
+            None => {
+                let field_accessor = syn::Index::from(i);
+                (quote! { self.#field_accessor }, field, attrs)
+            }
+        });
+
+    let rendered_field = match (rendered_fields.next(), rendered_fields.next()) {
+        (Some(rendered_field), None) => rendered_field,
+        _ => {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "transparent requires exactly one field that is not skipped",
+            ))
+        }
+    };
+
+    let (_, _, attrs) = &rendered_field;
+    if attrs.flatten || attrs.skip.is_some() || attrs.rename.is_some() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "skip_if, flatten and rename are not supported for the field of a transparent struct",
+        ));
+    }
+
+    Ok(rendered_field)
+}
+
+// Adds the identifiers in `tokens` to `idents`
+fn collect_idents(tokens: TokenStream2, idents: &mut Vec<Ident>) {
+    for token in tokens {
+        match token {
+            TokenTree::Ident(ident) => idents.push(ident),
+            TokenTree::Group(group) => collect_idents(group.stream(), idents),
+            _ => {}
+        }
+    }
+}
+
+// Bounds type parameters by `TreeDisplay`, except those only used by fields rendered
+// with `with`, `debug` or `display`. The types of `debug` and `display` fields using
+// type parameters are bounded by `Debug` and `Display` instead
+fn add_trait_bounds(mut generics: Generics, data: &Data) -> Result<Generics> {
+    let fields: Vec<&Field> = match data {
+        Data::Struct(DataStruct { fields, .. }) => fields.iter().collect(),
+        Data::Enum(DataEnum { variants, .. }) => {
+            variants.iter().flat_map(|v| v.fields.iter()).collect()
+        }
+        Data::Union(_) => Vec::new(),
+    };
+    let type_params = generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect::<Vec<_>>();
+    let mut rendered_idents = Vec::new();
+    let mut format_idents = Vec::new();
+    let mut predicates: Vec<WherePredicate> = Vec::new();
+    for field in fields {
+        let ty = &field.ty;
+        let mut idents = Vec::new();
+        collect_idents(quote! { #ty }, &mut idents);
+        match parse_field_attributes(&field.attrs)?.format {
+            Some(format) => {
+                if idents.iter().any(|ident| type_params.contains(ident)) {
+                    match format {
+                        FieldFormat::Debug => predicates.push(parse_quote!(#ty: ::std::fmt::Debug)),
+                        FieldFormat::Display => {
+                            predicates.push(parse_quote!(#ty: ::std::fmt::Display))
+                        }
+                        FieldFormat::With(_) => {}
                     }
-                    writeln!(f)?;
-                    tree_display::TreeDisplay::tree_fmt(&self.#field_accessor, f, tree_display::Context { indent: &indent_modified, ..ctx }, Default::default())?;
                 }
+                format_idents.extend(idents);
             }
-        })
+            None => rendered_idents.extend(idents),
+        }
+    }
+    for param in generics.type_params_mut() {
+        if !format_idents.contains(&param.ident) || rendered_idents.contains(&param.ident) {
+            param.bounds.push(parse_quote!(tree_display::TreeDisplay));
+        }
+    }
+    generics.make_where_clause().predicates.extend(predicates);
+    Ok(generics)
 }
 
 fn impl_my_trait(ast: DeriveInput) -> Result<TokenStream2> {
     Ok({
         let name = ast.ident;
-        let where_clause = ast.generics.where_clause.clone();
-        let generics = ast.generics;
+        let generics = add_trait_bounds(ast.generics.clone(), &ast.data)?;
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
         let attrs = parse_container_attributes(&ast.attrs)?;
 
+        if attrs.tag.is_some() && !matches!(ast.data, Data::Enum(_)) {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "tag, content and untagged are only supported for enums",
+            ));
+        }
+
+        if attrs.debug_leaf && (attrs.transparent || attrs.tag.is_some()) {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "debug_leaf cannot be combined with transparent, tag, content or untagged",
+            ));
+        }
+
+        if attrs.debug_leaf {
+            let name_stringified = LitStr::new(&format!(" ({})", name), name.span());
+            let mut generics = ast.generics.clone();
+            generics
+                .make_where_clause()
+                .predicates
+                .push(parse_quote!(Self: ::std::fmt::Debug));
+            let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+            return Ok(quote! {
+                impl #impl_generics tree_display::TreeDisplay for #name #ty_generics #where_clause {
+                    fn to_tree_node(&self, ctx: tree_display::Context) -> tree_display::TreeNode {
+                        tree_display::debug_leaf_node(self, ctx)
+                    }
+
+                    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                        write!(f, "{}", #name_stringified)
+                    }
+
+                    fn is_leaf(&self) -> bool {
+                        true
+                    }
+                }
+            });
+        }
+
+        if attrs.transparent {
+            let (field_accessor, field, field_attrs) = gen_transparent_accessor(&ast.data)?;
+            let name_stringified = LitStr::new(&name.to_string(), name.span());
+            let max_items_code = field_attrs
+                .max_items
+                .map(|max_items| quote! { max_items: Some(#max_items), });
+            let truncate_code = field_attrs
+                .truncate
+                .map(|truncate| quote! { max_leaf_width: Some(#truncate), });
+            if let Some(format) = field_attrs.format {
+                let type_name = LitStr::new(
+                    &format!(" ({}) -> ({})", name, type_label(&field.ty)),
+                    name.span(),
+                );
+                let node_code = gen_format_leaf(
+                    format,
+                    quote! { &#field_accessor },
+                    quote! { #max_items_code #truncate_code },
+                );
+                return Ok(quote! {
+                    impl #impl_generics tree_display::TreeDisplay for #name #ty_generics #where_clause {
+                        fn to_tree_node(&self, ctx: tree_display::Context) -> tree_display::TreeNode {
+                            #node_code
+                        }
+
+                        fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                            write!(f, "{}", #type_name)
+                        }
+
+                        fn is_leaf(&self) -> bool {
+                            true
+                        }
+                    }
+                });
+            }
+            return Ok(quote! {
+                impl #impl_generics tree_display::TreeDisplay for #name #ty_generics #where_clause {
+                    fn to_tree_node(&self, ctx: tree_display::Context) -> tree_display::TreeNode {
+                        tree_display::TreeDisplay::to_tree_node(&#field_accessor, tree_display::Context { #max_items_code #truncate_code ..ctx })
+                    }
+
+                    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                        write!(f, " ({}) ->", #name_stringified)?;
+                        tree_display::TreeDisplay::type_name_fmt(&#field_accessor, f)
+                    }
+
+                    fn is_flatten_empty(&self) -> bool {
+                        tree_display::TreeDisplay::is_flatten_empty(&#field_accessor)
+                    }
+
+                    fn is_leaf(&self) -> bool {
+                        tree_display::TreeDisplay::is_leaf(&#field_accessor)
+                    }
+                }
+            });
+        }
+
         match ast.data {
             Data::Enum(DataEnum {
                 enum_token: token::Enum { span: _ },
@@ -606,116 +1058,258 @@ fn impl_my_trait(ast: DeriveInput) -> Result<TokenStream2> {
                 let name_span = name.span();
                 let name_stringified = LitStr::new(&name.to_string(), name_span);
 
+                let mut leaf_arms = Vec::new();
                 let variants_code = variants.into_iter().map(|v| {
-                    let variant_name_stringified =
-                        LitStr::new(&v.ident.to_string(), v.ident.span());
+                    let variant_attrs = parse_variant_attributes(&v.attrs)?;
+                    // As in serde, `rename_all` on an enum renames its variants, and so the tag
+                    // values, while `rename_all` on a variant renames that variant's fields
+                    let variant_name_string = variant_attrs
+                        .rename
+                        .as_ref()
+                        .or(attrs.rename_all.as_ref())
+                        .map(|rename_type| rename_named_field(v.ident.to_string(), rename_type))
+                        .unwrap_or_else(|| v.ident.to_string());
+                    let variant_name_stringified = LitStr::new(&variant_name_string, v.ident.span());
                     let variant_name = v.ident;
 
-                    let variant_name_code = quote! {
-                        writeln!(f, "{}└──{}", ctx.indent, #variant_name_stringified)?;
-                        if let Some(sparcity) = ctx.sparcity {
-                            (0..sparcity.get()).try_for_each(|_| {
-                                writeln!(f, "{}   |", ctx.indent)
-                            })?;
+                    // Skipped variants leave a placeholder leaf, so a field holding one keeps a value
+                    let skip_code = match variant_attrs.skip {
+                        Some(SkipType::Always) => {
+                            leaf_arms.push(quote! { #name::#variant_name { .. } => true, });
+                            return Ok(quote! {
+                                #name::#variant_name { .. } => tree_display::TreeNode::leaf("<skipped>"),
+                            });
                         }
-                        let mut indent_modified = ctx.indent.to_string();
-                        indent_modified.push_str("   ");
+                        Some(SkipType::If(skip_if)) => {
+                            leaf_arms.push(quote! { #name::#variant_name { .. } if #skip_if (self) => true, });
+                            quote! {
+                                #name::#variant_name { .. } if #skip_if (self) => tree_display::TreeNode::leaf("<skipped>"),
+                            }
+                        }
+                        _ => quote! {},
                     };
 
-                    match v.fields {
+                    let is_unit = matches!(v.fields, Fields::Unit);
+                    let mut i = 0;
+                    let field_idents = v
+                        .fields
+                        .iter()
+                        .map(|f| f.ident.clone())
+                        .map(|f| {
+                            f.unwrap_or_else(|| {
+                                i += 1;
+                                Ident::new(&format!("__field_{}", i), Span::call_site())
+                            })
+                        })
+                        .collect::<Vec<_>>();
+
+                    // How the single field of a newtype variant is checked for being a leaf and
+                    // laid out, its node is used in place of the variant payload
+                    let (pattern_code, payload_code, newtype, is_empty) = match v.fields {
                         Fields::Named(fields) => {
-                            let span = name.span();
-                            let name_stringified = LitStr::new(&name.to_string(), span);
-                            let mut i = 0;
-                            let fields_with_names = fields
-                                .named
-                                .iter()
-                                .map(|f| f.ident.clone())
-                                .map(|f| {
-                                    f.unwrap_or_else(|| {
-                                        i += 1;
-                                        Ident::new(&format!("__field_{}", i), Span::call_site())
+                            let is_empty = fields.named.is_empty();
+                            let named_fields_code = gen_named_fields(fields, variant_attrs.rename_all)?;
+                            (
+                                quote! { #name::#variant_name { #(#field_idents ,)* } },
+                                named_fields_code,
+                                None,
+                                is_empty,
+                            )
+                        }
+                        Fields::Unnamed(fields) => {
+                            let accessors = field_idents.iter().map(|ident| quote! { (*#ident) }).collect();
+                            let unnamed_fields_code = gen_unnamed_fields(&fields, accessors)?;
+                            let newtype = match (fields.unnamed.first(), field_idents.as_slice()) {
+                                (Some(field), [field_ident]) => {
+                                    let field_attrs = parse_field_attributes(&field.attrs)?;
+                                    let max_items_code = field_attrs.max_items.map(|max_items| quote! { max_items: Some(#max_items), });
+                                    let truncate_code = field_attrs.truncate.map(|truncate| quote! { max_leaf_width: Some(#truncate), });
+                                    Some(match field_attrs.format {
+                                        Some(format) => (quote! { true }, gen_format_leaf(format, quote! { #field_ident }, quote! { #max_items_code #truncate_code })),
+                                        None => (
+                                            quote! { tree_display::TreeDisplay::is_leaf(#field_ident) },
+                                            quote! { tree_display::TreeDisplay::to_tree_node(#field_ident, tree_display::Context { #max_items_code #truncate_code ..ctx }) },
+                                        ),
                                     })
-                                })
-                                .collect::<Vec<_>>();
-                            let destructure_code = quote! {
-                                #(#fields_with_names ,)*
+                                }
+                                _ => None,
                             };
+                            (
+                                quote! { #name::#variant_name(#(#field_idents ,)*) },
+                                unnamed_fields_code,
+                                newtype,
+                                field_idents.is_empty(),
+                            )
+                        }
+                        Fields::Unit => (quote! { #name::#variant_name }, quote! {}, None, true),
+                    };
 
-                            let named_fields_code = gen_named_fields(fields, attrs.rename_all.clone())?;
-                            Ok(quote! {
-                                #name::#variant_name { #destructure_code } => {
-                                    #variant_name_code
-                                    let mut indent = ctx.indent.to_string();
-                                    indent.push_str("   ");
+                    let payload_node_code = quote! {
+                        {
+                            #[allow(unused_mut)]
+                            let mut __children = ::std::vec::Vec::new();
+                            #payload_code
+                            tree_display::TreeNode::branch(__children)
+                        }
+                    };
+
+                    let arm_code = match &attrs.tag {
+                        None => {
+                            if is_unit {
+                                leaf_arms.push(quote! { #pattern_code => true, });
+                                quote! { tree_display::TreeNode::unit_variant(#variant_name_stringified) }
+                            } else {
+                                let (is_elided_code, variant_code) = if let Some((is_leaf_code, node_code)) = &newtype {
+                                    (
+                                        quote! { !#is_leaf_code && ctx.is_depth_exhausted() },
+                                        quote! {
+                                            tree_display::TreeNode::variant_payload({
+                                                let ctx = tree_display::Context { depth: ctx.depth + 1, ..ctx };
+                                                #node_code
+                                            })
+                                        },
+                                    )
+                                } else {
+                                    (quote! { !#is_empty && ctx.is_depth_exhausted() }, payload_node_code)
+                                };
+                                quote! {
                                     let ctx = tree_display::Context {
-                                        indent: &indent,
+                                        depth: ctx.depth + 1,
                                         ..ctx
                                     };
-                                    #named_fields_code
+                                    let variant = if #is_elided_code {
+                                        tree_display::elision_node(ctx, if ctx.count_elided {
+                                            tree_display::count_hidden(|ctx| #variant_code)
+                                        } else {
+                                            0
+                                        })
+                                    } else {
+                                        #variant_code
+                                    };
+                                    tree_display::TreeNode::branch(vec![tree_display::TreeNode {
+                                        label: Some(#variant_name_stringified.to_string()),
+                                        ..variant
+                                    }])
                                 }
-                            })
+                            }
                         }
-                        Fields::Unnamed(unnamed) => {
-                            let mut i = 0;
-                            let fields = unnamed
-                                .unnamed
-                                .iter()
-                                .map(|f| f.ident.clone())
-                                .map(|f| {
-                                    f.unwrap_or_else(|| {
-                                        i += 1;
-                                        Ident::new(&format!("__field_{}", i), Span::call_site())
-                                    })
-                                })
-                                .collect::<Vec<_>>();
-                            let destructure_code = quote! {
-                                #(#fields ,)*
+                        Some(TagType::Untagged) => {
+                            if let Some((is_leaf_code, node_code)) = &newtype {
+                                leaf_arms.push(quote! {
+                                    #pattern_code => #is_leaf_code,
+                                });
+                                node_code.clone()
+                            } else if is_empty {
+                                quote! { tree_display::TreeNode::hidden() }
+                            } else {
+                                payload_node_code
+                            }
+                        }
+                        Some(TagType::Tagged { tag, content: None }) => {
+                            let tag_code = gen_tag_code(tag, &variant_name_stringified);
+                            let payload_code = if let Some((_, node_code)) = &newtype {
+                                quote! {
+                                    #node_code.flatten_into(&mut __children);
+                                }
+                            } else {
+                                payload_code
                             };
-                            let fields_fmt = fields.iter().map(|ident| {
+                            quote! {
+                                #[allow(unused_mut)]
+                                let mut __children = ::std::vec::Vec::new();
+                                #tag_code
+                                #payload_code
+                                tree_display::TreeNode::branch(__children)
+                            }
+                        }
+                        Some(TagType::Tagged { tag, content: Some(content) }) => {
+                            let tag_code = gen_tag_code(tag, &variant_name_stringified);
+                            let content_stringified = LitStr::new(content, Span::call_site());
+                            let (is_elided_code, content_code) = if let Some((is_leaf_code, node_code)) = &newtype {
+                                (
+                                    quote! { !#is_leaf_code && ctx.is_depth_exhausted() },
+                                    quote! {
+                                        {
+                                            let ctx = tree_display::Context { depth: ctx.depth + 1, ..ctx };
+                                            #node_code
+                                        }
+                                    },
+                                )
+                            } else {
+                                (quote! { ctx.is_depth_exhausted() }, payload_node_code)
+                            };
+                            let content_code = if is_empty {
+                                quote! {}
+                            } else {
                                 quote! {
-                                    #ident.tree_fmt(f, tree_display::Context { indent: &indent_modified, ..ctx }, Default::default())?;
+                                    let content = {
+                                        let ctx = tree_display::Context {
+                                            depth: ctx.depth + 1,
+                                            ..ctx
+                                        };
+                                        if #is_elided_code {
+                                            tree_display::elision_node(ctx, if ctx.count_elided {
+                                                tree_display::count_hidden(|ctx| #content_code)
+                                            } else {
+                                                0
+                                            })
+                                        } else {
+                                            #content_code
+                                        }
+                                    };
+                                    __children.push(tree_display::TreeNode {
+                                        label: Some(#content_stringified.to_string()),
+                                        ..content
+                                    });
                                 }
-                            });
+                            };
+                            quote! {
+                                let mut __children = ::std::vec::Vec::new();
+                                #tag_code
+                                #content_code
+                                tree_display::TreeNode::branch(__children)
+                            }
+                        }
+                    };
 
-                            Ok(quote! {
-                                #name::#variant_name(#destructure_code) => {
-                                    #variant_name_code
-                                    #(#fields_fmt)*
-                                }
-                            })
+                    Ok(quote! {
+                        #skip_code
+                        #pattern_code => {
+                            #arm_code
                         }
-                        Fields::Unit => Ok(quote! {
-                                #name::#variant_name => {
-                                    writeln!(f, "{}└──{}", ctx.indent, #variant_name_stringified)?;
-                                    if let Some(sparcity) = ctx.sparcity {
-                                        (0..sparcity.get()).try_for_each(|_| {
-                                            writeln!(f, "{}", ctx.indent)
-                                        })?;
-                                    }
-                                    let mut indent_modified = ctx.indent.to_string();
-                            }
-                        }),
-                    }
+                    })
                 }).collect::<Result<Vec<_>>>()?;
 
+                let is_leaf_code = if leaf_arms.is_empty() {
+                    quote! { false }
+                } else {
+                    quote! {
+                        #[allow(unreachable_patterns)]
+                        match self {
+                            #(#leaf_arms)*
+                            _ => false,
+                        }
+                    }
+                };
+
                 quote! {
-                    impl tree_display::TreeDisplay for #name {
-                        fn tree_fmt(&self, f: &mut ::std::fmt::Formatter<'_>, ctx: tree_display::Context, tctx: tree_display::TransientContext) -> ::std::fmt::Result {
-                            if let Some(sparcity) = ctx.sparcity {
-                                (0..sparcity.get()).try_for_each(|_| {
-                                    writeln!(f, "{}|", ctx.indent)
-                                })?;
+                    impl #impl_generics tree_display::TreeDisplay for #name #ty_generics #where_clause {
+                        fn to_tree_node(&self, ctx: tree_display::Context) -> tree_display::TreeNode {
+                            if let Some(node) = tree_display::elided_node(self, ctx) {
+                                return node;
                             }
                             match self {
                                 #(#variants_code)*
                             }
-                            Ok(())
                         }
 
                         fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                             write!(f, " ({})", #name_stringified)
                         }
+
+                        fn is_leaf(&self) -> bool {
+                            #is_leaf_code
+                        }
                     }
                 }
             }
@@ -735,17 +1329,16 @@ fn impl_my_trait(ast: DeriveInput) -> Result<TokenStream2> {
                 let named_fields_code = gen_named_fields(fields, attrs.rename_all)?;
 
                 quote! {
-                    impl tree_display::TreeDisplay for #name {
-                        fn tree_fmt(&self, f: &mut ::std::fmt::Formatter<'_>, ctx: tree_display::Context, tctx: tree_display::TransientContext) -> ::std::fmt::Result {
-                            #field_code
-
-                            if let Some(sparcity) = ctx.sparcity {
-                                (0..sparcity.get()).try_for_each(|_| {
-                                    writeln!(f, "{}|", ctx.indent)
-                                })?;
+                    impl #impl_generics tree_display::TreeDisplay for #name #ty_generics #where_clause {
+                        fn to_tree_node(&self, ctx: tree_display::Context) -> tree_display::TreeNode {
+                            if let Some(node) = tree_display::elided_node(self, ctx) {
+                                return node;
                             }
+                            #field_code
+                            #[allow(unused_mut)]
+                            let mut __children = ::std::vec::Vec::new();
                             #named_fields_code
-                            Ok(())
+                            tree_display::TreeNode::branch(__children)
                         }
 
                         fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -770,17 +1363,16 @@ fn impl_my_trait(ast: DeriveInput) -> Result<TokenStream2> {
 
                 let named_fields_code = gen_named_fields(fields, attrs.rename_all)?;
                 quote! {
-                    impl #generics tree_display::TreeDisplay for #name #generics #where_clause {
-                        fn tree_fmt(&self, f: &mut ::std::fmt::Formatter<'_>, ctx: tree_display::Context, tctx: tree_display::TransientContext) -> ::std::fmt::Result {
-                            #field_code
-
-                            if let Some(sparcity) = ctx.sparcity {
-                                (0..sparcity.get()).try_for_each(|_| {
-                                    writeln!(f, "{}|", ctx.indent)
-                                })?;
+                    impl #impl_generics tree_display::TreeDisplay for #name #ty_generics #where_clause {
+                        fn to_tree_node(&self, ctx: tree_display::Context) -> tree_display::TreeNode {
+                            if let Some(node) = tree_display::elided_node(self, ctx) {
+                                return node;
                             }
+                            #field_code
+                            #[allow(unused_mut)]
+                            let mut __children = ::std::vec::Vec::new();
                             #named_fields_code
-                            Ok(())
+                            tree_display::TreeNode::branch(__children)
                         }
 
                         fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -796,17 +1388,21 @@ fn impl_my_trait(ast: DeriveInput) -> Result<TokenStream2> {
             }) => {
                 let span = name.span();
                 let name_stringified = LitStr::new(&name.to_string(), span);
-                let unnamed_fields_code = gen_unnamed_fields(fields);
+                let accessors = (0..fields.unnamed.len())
+                    .map(syn::Index::from)
+                    .map(|field_accessor| quote! { self.#field_accessor })
+                    .collect();
+                let unnamed_fields_code = gen_unnamed_fields(&fields, accessors)?;
                 quote! {
-                    impl #generics tree_display::TreeDisplay for #name #generics #where_clause {
-                        fn tree_fmt(&self, f: &mut ::std::fmt::Formatter<'_>, ctx: tree_display::Context, tctx: tree_display::TransientContext) -> ::std::fmt::Result {
-                            if let Some(sparcity) = ctx.sparcity {
-                                (0..sparcity.get()).try_for_each(|_| {
-                                    writeln!(f, "{}|", ctx.indent)
-                                })?;
+                    impl #impl_generics tree_display::TreeDisplay for #name #ty_generics #where_clause {
+                        fn to_tree_node(&self, ctx: tree_display::Context) -> tree_display::TreeNode {
+                            if let Some(node) = tree_display::elided_node(self, ctx) {
+                                return node;
                             }
-                            #(#unnamed_fields_code)*
-                            Ok(())
+                            #[allow(unused_mut)]
+                            let mut __children = ::std::vec::Vec::new();
+                            #unnamed_fields_code
+                            tree_display::TreeNode::branch(__children)
                         }
 
                         fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -823,19 +1419,18 @@ fn impl_my_trait(ast: DeriveInput) -> Result<TokenStream2> {
                 let span = name.span();
                 let name_stringified = LitStr::new(&name.to_string(), span);
                 quote! {
-                    impl #generics tree_display::TreeDisplay for #name #generics #where_clause {
-                        fn tree_fmt(&self, f: &mut ::std::fmt::Formatter<'_>, ctx: tree_display::Context, tctx: tree_display::TransientContext) -> ::std::fmt::Result {
-                            if let Some(sparcity) = ctx.sparcity {
-                                (0..sparcity.get()).try_for_each(|_| {
-                                    writeln!(f, "{}", ctx.indent)
-                                })?;
-                            }
-                            Ok(())
+                    impl #impl_generics tree_display::TreeDisplay for #name #ty_generics #where_clause {
+                        fn to_tree_node(&self, _: tree_display::Context) -> tree_display::TreeNode {
+                            tree_display::TreeNode::default()
                         }
 
                         fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                             write!(f, " ({})", #name_stringified)
                         }
+
+                        fn is_flatten_empty(&self) -> bool {
+                            true
+                        }
                     }
                 }
             }
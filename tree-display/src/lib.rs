@@ -1,236 +1,1756 @@
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, LinkedList, VecDeque};
 use std::num::NonZeroUsize;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, RwLock, TryLockError};
 
 pub use tree_display_macros;
 
+// The glyphs used to draw the tree, all guides and connectors are three columns wide
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    pub branch: &'static str,
+    pub last_branch: &'static str,
+    pub short_branch: &'static str,
+    pub short_last_branch: &'static str,
+    pub vertical: &'static str,
+    pub guide: &'static str,
+    pub blank: &'static str,
+    pub bullet: &'static str,
+}
+
+impl Style {
+    pub const DEFAULT: Style = Style {
+        branch: "├──",
+        last_branch: "└──",
+        short_branch: "├─",
+        short_last_branch: "└─",
+        vertical: "|",
+        guide: "|  ",
+        blank: "   ",
+        bullet: "•",
+    };
+
+    // Edit friendly, for logs and consoles without box-drawing characters
+    pub const ASCII: Style = Style {
+        branch: "+--",
+        last_branch: "\\--",
+        short_branch: "+-",
+        short_last_branch: "\\-",
+        vertical: "|",
+        guide: "|  ",
+        blank: "   ",
+        bullet: "*",
+    };
+
+    pub const LIGHT: Style = Style {
+        branch: "├──",
+        last_branch: "└──",
+        short_branch: "├─",
+        short_last_branch: "└─",
+        vertical: "│",
+        guide: "│  ",
+        blank: "   ",
+        bullet: "•",
+    };
+
+    pub const HEAVY: Style = Style {
+        branch: "┣━━",
+        last_branch: "┗━━",
+        short_branch: "┣━",
+        short_last_branch: "┗━",
+        vertical: "┃",
+        guide: "┃  ",
+        blank: "   ",
+        bullet: "•",
+    };
+
+    pub const ROUNDED: Style = Style {
+        branch: "├──",
+        last_branch: "╰──",
+        short_branch: "├─",
+        short_last_branch: "╰─",
+        vertical: "│",
+        guide: "│  ",
+        blank: "   ",
+        bullet: "•",
+    };
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct Context<'a> {
     pub indent: &'a str,
     pub sparcity: Option<NonZeroUsize>,
     pub show_types: bool,
     pub rename: Option<&'a str>,
+    pub inline_leaves: bool,
+    pub style: Style,
+    pub bullets: bool,
+    pub shared_refs: Option<&'a SharedRefs>,
+    pub max_depth: Option<usize>,
+    pub depth: usize,
+    pub count_elided: bool,
+    pub max_items: Option<usize>,
+    pub multiline_leaves: bool,
+    pub max_leaf_width: Option<usize>,
+}
+
+impl Context<'_> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Whether the children of the node being rendered are below `max_depth`
+    pub fn is_depth_exhausted(&self) -> bool {
+        matches!(self.max_depth, Some(max_depth) if self.depth >= max_depth)
+    }
+
+    // The range of collection items hidden by `max_items`, which keeps the first half
+    // of the shown items at the start and the rest at the end
+    pub fn hidden_items(&self, len: usize) -> std::ops::Range<usize> {
+        match self.max_items {
+            Some(max_items) if len > max_items => {
+                let head = max_items - max_items / 2;
+                head..len - (max_items - head)
+            }
+            _ => 0..0,
+        }
+    }
+}
+
+// Pointer identities seen during one render, numbered in order of first appearance.
+// Setting `Context.shared_refs` renders repeated `Rc`/`Arc` values once, which also
+// makes rendering terminate on cyclic graphs
+#[derive(Debug, Default)]
+pub struct SharedRefs {
+    ids: RefCell<HashMap<*const (), usize>>,
+}
+
+impl SharedRefs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&self) {
+        self.ids.borrow_mut().clear();
+    }
+
+    // Returns the id of the pointer and whether it was seen before
+    fn visit(&self, ptr: *const ()) -> (usize, bool) {
+        let mut ids = self.ids.borrow_mut();
+        let next_id = ids.len() + 1;
+        match ids.entry(ptr) {
+            std::collections::hash_map::Entry::Occupied(entry) => (*entry.get(), true),
+            std::collections::hash_map::Entry::Vacant(entry) => (*entry.insert(next_id), false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransientContext {}
+
+impl TransientContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// How a node hangs off its parent, which decides the connector it is drawn with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NodeKind {
+    // The node the tree starts from, see `TreeNode::root`
+    Root,
+    // Struct and tuple fields, enum variants and tags
+    #[default]
+    Field,
+    // Sequence items, drawn as bullets when `ctx.bullets` is set
+    Item,
+    // Map entries, `Result` variants and shared pointers
+    Entry,
+    // The `… N more items` placeholder for the items hidden by `ctx.max_items`
+    Marker,
+}
+
+// Spacing the text renderer keeps from the renderer that predates `TreeNode`, for the few
+// nodes it drew differently from others of the same shape. It is opaque outside the
+// renderer, nodes get one from the constructors of `TreeNode` and other formats ignore it
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Layout(Spacing);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Spacing {
+    #[default]
+    Plain,
+    // Drawn below a gap, like `None`
+    Gapped,
+    // A unit variant, drawn below a gap with the connector of the other variant names
+    Variant,
+    // Children drawn without the gap above them, like the `&id` of a shared pointer
+    Gapless,
+    // A leaf that stays below its label when `ctx.inline_leaves` is set
+    Block,
+    // Not drawn at all, like untagged unit variants
+    Hidden,
+    // Drawn one level deeper than its parent puts it, like the variants of `Result`
+    Indented,
+}
+
+// A value laid out as a tree, independent of how it is rendered. Leaves hold a `value`
+// and other nodes their `children`, `label` and `type_name` are set by the parent
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct TreeNode {
+    pub label: Option<String>,
+    pub value: Option<String>,
+    pub type_name: Option<String>,
+    pub children: Vec<TreeNode>,
+    pub kind: NodeKind,
+    pub layout: Layout,
+}
+
+// Leaves out the layout, which only the text renderer reads
+impl std::fmt::Debug for TreeNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TreeNode")
+            .field("label", &self.label)
+            .field("value", &self.value)
+            .field("type_name", &self.type_name)
+            .field("children", &self.children)
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+impl TreeNode {
+    pub fn leaf(value: impl Into<String>) -> Self {
+        Self {
+            value: Some(value.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn branch(children: Vec<TreeNode>) -> Self {
+        Self {
+            children,
+            ..Self::default()
+        }
+    }
+
+    // The root node of `data`, labelled by `ctx.rename` when set.
+    // Starts a new numbering of shared pointers when `ctx.shared_refs` is set
+    pub fn root<T: TreeDisplay + ?Sized>(data: &T, ctx: Context) -> Self {
+        if let Some(shared_refs) = ctx.shared_refs {
+            shared_refs.clear();
+        }
+        Self {
+            label: ctx.rename.map(str::to_string),
+            type_name: Some(type_name(data)),
+            kind: NodeKind::Root,
+            ..data.to_tree_node(ctx)
+        }
+    }
+
+    // The node of `data` as a child labelled `label`, one level below `ctx.depth`
+    pub fn child<T: TreeDisplay + ?Sized>(
+        data: &T,
+        label: impl Into<String>,
+        kind: NodeKind,
+        ctx: Context,
+    ) -> Self {
+        Self {
+            label: Some(label.into()),
+            type_name: Some(type_name(data)),
+            kind,
+            ..data.to_tree_node(Context {
+                depth: ctx.depth + 1,
+                ..ctx
+            })
+        }
+    }
+
+    // The leaf of a unit enum variant
+    pub fn unit_variant(name: impl Into<String>) -> Self {
+        Self {
+            layout: Layout(Spacing::Variant),
+            ..Self::leaf(name)
+        }
+    }
+
+    // The node of the single field of a newtype variant, drawn below the variant name
+    pub fn variant_payload(node: TreeNode) -> Self {
+        Self {
+            layout: Layout(Spacing::Gapped),
+            ..node
+        }
+    }
+
+    // A node that is not shown, for variants without anything to show
+    pub fn hidden() -> Self {
+        Self {
+            layout: Layout(Spacing::Hidden),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.value.is_some()
+    }
+
+    // Adds the children of this node to `children` in place of the node itself,
+    // a leaf is added as is without its label
+    pub fn flatten_into(self, children: &mut Vec<TreeNode>) {
+        if self.is_leaf() {
+            children.push(Self {
+                label: None,
+                ..self
+            });
+        } else {
+            children.extend(self.children);
+        }
+    }
+
+    // Writes this node as the root of the tree, its label line followed by its body
+    fn root_fmt(&self, f: &mut std::fmt::Formatter<'_>, ctx: Context) -> std::fmt::Result {
+        let label = match &self.label {
+            Some(label) => label,
+            None => return self.body_fmt(f, ctx),
+        };
+        write!(f, "{}{}", ctx.indent, label)?;
+        self.type_name_fmt(f, ctx)?;
+        match self.inline_value() {
+            Some(value) if ctx.inline_leaves => {
+                write!(f, ": ")?;
+                value_fmt(f, value, ctx.indent)?;
+                writeln!(f)?;
+                gap_fmt(f, ctx, ctx.indent)
+            }
+            _ => {
+                writeln!(f)?;
+                self.body_fmt(f, ctx)
+            }
+        }
+    }
+
+    // Writes what goes below the label line of this node
+    fn body_fmt(&self, f: &mut std::fmt::Formatter<'_>, ctx: Context) -> std::fmt::Result {
+        let indent;
+        let ctx = match self.layout.0 {
+            Spacing::Hidden => return Ok(()),
+            Spacing::Indented => {
+                indent = format!("{}{}", ctx.indent, ctx.style.blank);
+                Context {
+                    indent: &indent,
+                    ..ctx
+                }
+            }
+            _ => ctx,
+        };
+        let vertical = format!("{}{}", ctx.indent, ctx.style.vertical);
+        if matches!(self.layout.0, Spacing::Gapped | Spacing::Variant) {
+            gap_fmt(f, ctx, &vertical)?;
+        }
+        if let Some(value) = &self.value {
+            let branch = match self.layout.0 {
+                Spacing::Variant => ctx.style.last_branch,
+                _ => ctx.style.short_last_branch,
+            };
+            write!(f, "{}{}", ctx.indent, branch)?;
+            let width = branch.chars().count();
+            value_fmt(
+                f,
+                value,
+                &format!("{}{:width$}", ctx.indent, "", width = width),
+            )?;
+            writeln!(f)?;
+            return gap_fmt(f, ctx, ctx.indent);
+        }
+        if self.children.is_empty() {
+            return gap_fmt(f, ctx, ctx.indent);
+        }
+        if self.layout.0 != Spacing::Gapless {
+            gap_fmt(f, ctx, &vertical)?;
+        }
+        let len = self.children.len();
+        self.children
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, child)| child.child_fmt(f, ctx, i + 1 == len))
+    }
+
+    // Writes this node below its parent, items lose their label when `ctx.bullets` is set
+    fn child_fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        ctx: Context,
+        is_last: bool,
+    ) -> std::fmt::Result {
+        let label = match self.kind {
+            NodeKind::Item if ctx.bullets => None,
+            _ => self.label.as_deref(),
+        };
+        self.labelled_fmt(f, ctx, label, is_last)
+    }
+
+    // Writes the connector and label line of this node followed by its body. Leaves go on
+    // the label line when `ctx.inline_leaves` is set, or on their own when there is no label
+    fn labelled_fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        ctx: Context,
+        label: Option<&str>,
+        is_last: bool,
+    ) -> std::fmt::Result {
+        let (branch, guide) = match (self.kind, is_last) {
+            (NodeKind::Field, false) => (ctx.style.branch, ctx.style.guide),
+            (NodeKind::Field, true) => (ctx.style.last_branch, ctx.style.blank),
+            (_, false) => (ctx.style.short_branch, ctx.style.guide),
+            (_, true) => (ctx.style.short_last_branch, ctx.style.blank),
+        };
+        write!(f, "{}{}", ctx.indent, branch)?;
+        let indent = format!("{}{}", ctx.indent, guide);
+        // Unit variants flattened into their parent keep the gap of the parent level
+        let gap = match (self.layout.0, is_last) {
+            (Spacing::Variant, false) => format!("{}{}", ctx.indent, ctx.style.vertical),
+            (Spacing::Variant, true) => ctx.indent.to_string(),
+            _ => indent.clone(),
+        };
+        let ctx = Context {
+            indent: &indent,
+            ..ctx
+        };
+        match (label, &self.value) {
+            (None, Some(value))
+                if self.kind != NodeKind::Item || self.layout.0 != Spacing::Block =>
+            {
+                value_fmt(f, value, &indent)?;
+                self.type_name_fmt(f, ctx)?;
+            }
+            (Some(label), Some(value)) if ctx.inline_leaves && self.layout.0 != Spacing::Block => {
+                write!(f, "{}", label)?;
+                self.type_name_fmt(f, ctx)?;
+                write!(f, ": ")?;
+                value_fmt(f, value, &indent)?;
+            }
+            (Some(label), None) if self.kind == NodeKind::Marker => {
+                write!(f, "{}", label)?;
+            }
+            (label, _) => {
+                write!(f, "{}", label.unwrap_or(ctx.style.bullet))?;
+                self.type_name_fmt(f, ctx)?;
+                writeln!(f)?;
+                return self.body_fmt(f, ctx);
+            }
+        }
+        writeln!(f)?;
+        gap_fmt(f, ctx, &gap)
+    }
+
+    // The value written after the label when `ctx.inline_leaves` is set
+    fn inline_value(&self) -> Option<&String> {
+        match self.layout.0 {
+            Spacing::Block => None,
+            _ => self.value.as_ref(),
+        }
+    }
+
+    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>, ctx: Context) -> std::fmt::Result {
+        match &self.type_name {
+            Some(type_name) if ctx.show_types => write!(f, " {}", type_name),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Writes a possibly multi-line value, the lines after the first prefixed by `indent`
+fn value_fmt(f: &mut std::fmt::Formatter<'_>, value: &str, indent: &str) -> std::fmt::Result {
+    let mut lines = value.lines();
+    write!(f, "{}", lines.next().unwrap_or_default())?;
+    lines.try_for_each(|line| write!(f, "\n{}{}", indent, line))
+}
+
+// Writes the blank lines between siblings of a sparse tree
+fn gap_fmt(f: &mut std::fmt::Formatter<'_>, ctx: Context, line: &str) -> std::fmt::Result {
+    if let Some(sparcity) = ctx.sparcity {
+        (0..sparcity.get()).try_for_each(|_| writeln!(f, "{}", line))?;
+    }
+    Ok(())
+}
+
+fn type_name<T: TreeDisplay + ?Sized>(data: &T) -> String {
+    type_name_from(|f| data.type_name_fmt(f))
+}
+
+// The type name written by a `type_name_fmt` call, without its leading space
+pub fn type_name_from(
+    type_name_fmt: impl Fn(&mut std::fmt::Formatter<'_>) -> std::fmt::Result,
+) -> String {
+    struct TypeName<F>(F);
+
+    impl<F> std::fmt::Display for TypeName<F>
+    where
+        F: Fn(&mut std::fmt::Formatter<'_>) -> std::fmt::Result,
+    {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            (self.0)(f)
+        }
+    }
+
+    TypeName(type_name_fmt).to_string().trim_start().to_string()
+}
+
+impl TreeNode {
+    // The node as pretty-printed JSON. Every node is an object with the keys `label`, `kind`,
+    // `type`, `value` and `children` in that order, missing ones being `null`. The `type` holds
+    // the bare type names, a pointer and what it points to being joined like `Rc -> T`
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        self.json_fmt(&mut json, "");
+        json
+    }
+
+    fn json_fmt(&self, json: &mut String, indent: &str) {
+        let kind = match self.kind {
+            NodeKind::Root => "root",
+            NodeKind::Field => "field",
+            NodeKind::Item => "item",
+            NodeKind::Entry => "entry",
+            NodeKind::Marker => "marker",
+        };
+        let inner = format!("{}  ", indent);
+        json.push_str("{\n");
+        json.push_str(&format!(
+            "{}\"label\": {},\n",
+            inner,
+            json_string(self.label.as_deref())
+        ));
+        json.push_str(&format!("{}\"kind\": \"{}\",\n", inner, kind));
+        json.push_str(&format!(
+            "{}\"type\": {},\n",
+            inner,
+            json_string(self.type_name.as_deref().map(bare_type_name).as_deref())
+        ));
+        json.push_str(&format!(
+            "{}\"value\": {},\n",
+            inner,
+            json_string(self.value.as_deref())
+        ));
+        json.push_str(&format!("{}\"children\": [", inner));
+        for (i, child) in self.children.iter().enumerate() {
+            json.push_str(if i == 0 { "\n" } else { ",\n" });
+            json.push_str(&inner);
+            json.push_str("  ");
+            child.json_fmt(json, &format!("{}  ", inner));
+        }
+        if !self.children.is_empty() {
+            json.push('\n');
+            json.push_str(&inner);
+        }
+        json.push_str("]\n");
+        json.push_str(indent);
+        json.push('}');
+    }
+}
+
+// The type names in `type_name` without the parentheses and arrows written around them
+// by `type_name_fmt`, like `Rc -> T` for `(Rc) -> (T)`
+fn bare_type_name(type_name: &str) -> String {
+    let mut names = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in type_name.char_indices() {
+        match c {
+            '(' => {
+                if depth == 0 {
+                    start = i + 1;
+                }
+                depth += 1;
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    names.push(&type_name[start..i]);
+                }
+            }
+            _ => {}
+        }
+    }
+    if names.is_empty() {
+        return type_name.trim().to_string();
+    }
+    names.join(" -> ")
+}
+
+// A JSON string literal, or `null`
+fn json_string(text: Option<&str>) -> String {
+    let text = match text {
+        Some(text) => text,
+        None => return "null".to_string(),
+    };
+    let mut json = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+impl TreeNode {
+    // The node as a Graphviz digraph named by its label. Nodes with children become record
+    // nodes headed by their type, with leaf children as `label: value` rows and edges
+    // labelled by field name or index to the other children
+    pub fn to_dot(&self) -> String {
+        let mut dot = match &self.label {
+            Some(label) => format!("digraph {} {{\n", dot_string(label)),
+            None => "digraph {\n".to_string(),
+        };
+        dot.push_str("    node [shape=record];\n");
+        self.dot_fmt(&mut dot, &mut 0);
+        dot.push('}');
+        dot
+    }
+
+    fn dot_fmt(&self, dot: &mut String, next_id: &mut usize) {
+        let id = *next_id;
+        *next_id += 1;
+        let header = self.type_name.as_ref().or(self.label.as_ref());
+        let mut rows = vec![dot_record_escape(header.map_or("", String::as_str))];
+        rows.extend(self.value.as_deref().map(dot_record_escape));
+        let mut branches = Vec::new();
+        for child in &self.children {
+            match (&child.label, &child.value) {
+                (Some(label), Some(value)) => {
+                    rows.push(dot_record_escape(&format!("{}: {}", label, value)))
+                }
+                (None, Some(value)) => rows.push(dot_record_escape(value)),
+                (Some(label), None) if child.kind == NodeKind::Marker => {
+                    rows.push(dot_record_escape(label))
+                }
+                _ => branches.push(child),
+            }
+        }
+        dot.push_str(&format!(
+            "    n{} [label=\"{{{}}}\"];\n",
+            id,
+            rows.join("|")
+        ));
+        for child in branches {
+            let label = child.label.as_deref().unwrap_or_default();
+            dot.push_str(&format!(
+                "    n{} -> n{} [label={}];\n",
+                id,
+                *next_id,
+                dot_string(label)
+            ));
+            child.dot_fmt(dot, next_id);
+        }
+    }
+}
+
+// A quoted DOT string
+fn dot_string(text: &str) -> String {
+    let mut dot = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                dot.push('\\');
+                dot.push(c);
+            }
+            '\n' => dot.push_str("\\n"),
+            c => dot.push(c),
+        }
+    }
+    dot.push('"');
+    dot
+}
+
+// Escapes text for a record label, where braces, bars and angle brackets delimit fields
+fn dot_record_escape(text: &str) -> String {
+    let mut dot = String::new();
+    for c in text.chars() {
+        match c {
+            '"' | '\\' | '{' | '}' | '|' | '<' | '>' => {
+                dot.push('\\');
+                dot.push(c);
+            }
+            '\n' => dot.push_str("\\n"),
+            c => dot.push(c),
+        }
+    }
+    dot
+}
+
+impl TreeNode {
+    // The node as a Mermaid flowchart, one node per tree node captioned as in `caption`
+    pub fn to_mermaid(&self) -> String {
+        let mut lines = vec![
+            "graph TD".to_string(),
+            format!("    n0[{}]", mermaid_string(&self.caption())),
+        ];
+        self.mermaid_fmt(&mut lines, 0, &mut 1);
+        lines.join("\n")
+    }
+
+    fn mermaid_fmt(&self, lines: &mut Vec<String>, id: usize, next_id: &mut usize) {
+        for child in &self.children {
+            let child_id = *next_id;
+            *next_id += 1;
+            lines.push(format!(
+                "    n{} --> n{}[{}]",
+                id,
+                child_id,
+                mermaid_string(&child.caption())
+            ));
+            child.mermaid_fmt(lines, child_id, next_id);
+        }
+    }
+
+    // The node as a PlantUML mind map, one node per tree node captioned as in `caption`
+    pub fn to_plantuml(&self) -> String {
+        let mut lines = vec!["@startmindmap".to_string()];
+        self.plantuml_fmt(&mut lines, 1);
+        lines.push("@endmindmap".to_string());
+        lines.join("\n")
+    }
+
+    fn plantuml_fmt(&self, lines: &mut Vec<String>, depth: usize) {
+        let caption = plantuml_escape(&self.caption());
+        if caption.contains('\n') {
+            lines.push(format!("{} :{};", "*".repeat(depth), caption));
+        } else {
+            lines.push(format!("{} {}", "*".repeat(depth), caption));
+        }
+        for child in &self.children {
+            child.plantuml_fmt(lines, depth + 1);
+        }
+    }
+
+    // The text of the node in diagrams, `label: value` for leaves and `label (type)` otherwise
+    fn caption(&self) -> String {
+        match (&self.label, &self.value) {
+            (Some(label), Some(value)) => format!("{}: {}", label, value),
+            (None, Some(value)) => value.clone(),
+            (label, None) => {
+                let parts = [label.as_deref(), self.type_name.as_deref()];
+                parts.into_iter().flatten().collect::<Vec<_>>().join(" ")
+            }
+        }
+    }
+}
+
+// Escapes with `~` the characters PlantUML would read as creole markup, which are tags and
+// doubled characters such as `**` and `__`
+fn plantuml_escape(text: &str) -> String {
+    let mut plantuml = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let is_markup = match c {
+            '<' => true,
+            '*' | '/' | '"' | '-' | '_' | '~' | '=' => chars.peek() == Some(&c),
+            _ => false,
+        };
+        if is_markup {
+            plantuml.push('~');
+        }
+        plantuml.push(c);
+    }
+    plantuml
+}
+
+// A quoted Mermaid string, with characters that would end the string or be read as markup
+// written as entity codes
+fn mermaid_string(text: &str) -> String {
+    let mut mermaid = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => mermaid.push_str("#quot;"),
+            '#' => mermaid.push_str("#35;"),
+            '<' => mermaid.push_str("#lt;"),
+            '>' => mermaid.push_str("#gt;"),
+            '\n' => mermaid.push_str("<br>"),
+            c => mermaid.push(c),
+        }
+    }
+    mermaid.push('"');
+    mermaid
+}
+
+const HTML_STYLE: &str = "body { font-family: monospace; }
+ul { list-style: none; margin: 0; padding-left: 1.5em; border-left: 1px solid #ccc; }
+summary { cursor: pointer; }
+.label { font-weight: bold; }
+.type { color: #888; }
+.value { color: #05a; white-space: pre-wrap; }
+.marker { color: #888; font-style: italic; }";
+
+impl TreeNode {
+    // The node as a self-contained HTML page. Nodes with children are collapsible `<details>`
+    // elements, open at the root, and leaves are shown with their type
+    pub fn to_html(&self) -> String {
+        let title = self.label.as_ref().or(self.type_name.as_ref());
+        let mut lines = vec![
+            "<!DOCTYPE html>".to_string(),
+            "<html>".to_string(),
+            "<head>".to_string(),
+            "<meta charset=\"utf-8\">".to_string(),
+            format!(
+                "<title>{}</title>",
+                html_escape(title.map_or("", String::as_str))
+            ),
+            "<style>".to_string(),
+            HTML_STYLE.to_string(),
+            "</style>".to_string(),
+            "</head>".to_string(),
+            "<body>".to_string(),
+        ];
+        self.html_fmt(&mut lines, "", true);
+        lines.push("</body>".to_string());
+        lines.push("</html>".to_string());
+        lines.join("\n")
+    }
+
+    fn html_fmt(&self, lines: &mut Vec<String>, indent: &str, is_open: bool) {
+        if self.children.is_empty() {
+            lines.push(format!("{}<div>{}</div>", indent, self.html_caption()));
+            return;
+        }
+        let inner = format!("{}  ", indent);
+        let open = if is_open { " open" } else { "" };
+        lines.push(format!("{}<details{}>", indent, open));
+        lines.push(format!(
+            "{}<summary>{}</summary>",
+            inner,
+            self.html_caption()
+        ));
+        lines.push(format!("{}<ul>", inner));
+        for child in &self.children {
+            let item_indent = format!("{}  ", inner);
+            if child.children.is_empty() {
+                lines.push(format!("{}<li>{}</li>", item_indent, child.html_caption()));
+            } else {
+                lines.push(format!("{}<li>", item_indent));
+                child.html_fmt(lines, &format!("{}  ", item_indent), false);
+                lines.push(format!("{}</li>", item_indent));
+            }
+        }
+        lines.push(format!("{}</ul>", inner));
+        lines.push(format!("{}</details>", indent));
+    }
+
+    fn html_caption(&self) -> String {
+        if self.kind == NodeKind::Marker {
+            let label = self.label.as_deref().unwrap_or_default();
+            return format!("<span class=\"marker\">{}</span>", html_escape(label));
+        }
+        let mut parts = Vec::new();
+        if let Some(label) = &self.label {
+            parts.push(format!(
+                "<span class=\"label\">{}</span>",
+                html_escape(label)
+            ));
+        }
+        if let Some(type_name) = &self.type_name {
+            parts.push(format!(
+                "<span class=\"type\">{}</span>",
+                html_escape(type_name)
+            ));
+        }
+        let caption = parts.join(" ");
+        match &self.value {
+            Some(value) => {
+                let value = format!("<span class=\"value\">{}</span>", html_escape(value));
+                if caption.is_empty() {
+                    value
+                } else {
+                    format!("{}: {}", caption, value)
+                }
+            }
+            None => caption,
+        }
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    let mut html = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+pub trait TreeDisplay {
+    // Lays out this value as a tree, which all output formats are rendered from
+    fn to_tree_node(&self, ctx: Context) -> TreeNode;
+
+    fn tree_fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        ctx: Context, // Change to enum to show types, names or both? Also variants to rename any combination of the two
+        // dense or sparse
+        _: TransientContext,
+    ) -> std::fmt::Result {
+        self.to_tree_node(ctx).body_fmt(f, ctx)
+    }
+
+    // Renders the root node, labelled by `ctx.rename` when set, followed by its children
+    fn tree_root_fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        ctx: Context,
+        _: TransientContext,
+    ) -> std::fmt::Result {
+        TreeNode::root(self, ctx).root_fmt(f, ctx)
+    }
+
+    // The tree as JSON, see `TreeNode::to_json`
+    fn to_json(&self, ctx: Context) -> String {
+        TreeNode::root(self, ctx).to_json()
+    }
+
+    // The tree as a Graphviz digraph, see `TreeNode::to_dot`
+    fn to_dot(&self, ctx: Context) -> String {
+        TreeNode::root(self, ctx).to_dot()
+    }
+
+    // The tree as a Mermaid flowchart, see `TreeNode::to_mermaid`
+    fn to_mermaid(&self, ctx: Context) -> String {
+        TreeNode::root(self, ctx).to_mermaid()
+    }
+
+    // The tree as a PlantUML mind map, see `TreeNode::to_plantuml`
+    fn to_plantuml(&self, ctx: Context) -> String {
+        TreeNode::root(self, ctx).to_plantuml()
+    }
+
+    // The tree as an HTML page with collapsible nodes, see `TreeNode::to_html`
+    fn to_html(&self, ctx: Context) -> String {
+        TreeNode::root(self, ctx).to_html()
+    }
+
+    fn tree_print(&self, ctx: Context, tctx: TransientContext) -> String
+    where
+        Self: Sized,
+    {
+        DataContainer {
+            data: self,
+            ctx,
+            tctx,
+        }
+        .to_string()
+    }
+
+    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result;
+
+    // Whether flattening this value into its parent renders nothing at all,
+    // so the parent can pick the right connector for its last visible field
+    fn is_flatten_empty(&self) -> bool {
+        false
+    }
+
+    // Whether this value renders as a single value line, which `ctx.inline_leaves`
+    // puts on the same line as its label (`label: value`) instead of below it
+    fn is_leaf(&self) -> bool {
+        false
+    }
+}
+
+// The leaf of a value that does not implement `TreeDisplay`, through its `Debug` impl
+pub fn debug_leaf_node(value: &dyn std::fmt::Debug, ctx: Context) -> TreeNode {
+    text_leaf_node(&format!("{:?}", value), ctx)
+}
+
+// The leaf of a value that does not implement `TreeDisplay`, through its `Display` impl
+pub fn display_leaf_node(value: &dyn std::fmt::Display, ctx: Context) -> TreeNode {
+    text_leaf_node(&value.to_string(), ctx)
+}
+
+// The leaf of a value rendered by a `with` formatter, which writes it the way a leaf body is
+// written below its label. The formatter writes at no indent and without gaps. When its first
+// line starts with the connector and every later line with the continuation indent, as with
+// formatters written for the old renderer, these are dropped since the renderer draws its own.
+// Otherwise the lines are kept as written
+pub fn with_leaf_node(
+    ctx: Context,
+    with: &dyn Fn(&mut std::fmt::Formatter<'_>, Context) -> std::fmt::Result,
+) -> TreeNode {
+    struct WithFormatter<'a> {
+        ctx: Context<'a>,
+        with: &'a dyn Fn(&mut std::fmt::Formatter<'_>, Context) -> std::fmt::Result,
+    }
+
+    impl std::fmt::Display for WithFormatter<'_> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            (self.with)(f, self.ctx)
+        }
+    }
+
+    let ctx = Context {
+        indent: "",
+        sparcity: None,
+        inline_leaves: false,
+        ..ctx
+    };
+    let text = WithFormatter { ctx, with }.to_string();
+    let continuation = " ".repeat(ctx.style.short_last_branch.chars().count());
+    let stripped = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let prefix = if i == 0 {
+                ctx.style.short_last_branch
+            } else {
+                &continuation
+            };
+            line.strip_prefix(prefix)
+        })
+        .collect::<Option<Vec<_>>>();
+    let lines = stripped.unwrap_or_else(|| text.lines().collect());
+    TreeNode {
+        layout: Layout(Spacing::Block),
+        ..TreeNode::leaf(lines.join("\n"))
+    }
+}
+
+fn text_leaf_node(text: &str, ctx: Context) -> TreeNode {
+    TreeNode::leaf(truncate_leaf(text, ctx.max_leaf_width, text.len()))
+}
+
+// The leaf of a string, unescaped over several lines when `ctx.multiline_leaves` is set
+// and it contains newlines, otherwise quoted like other primitives. `ctx.max_leaf_width`
+// applies to the characters of the string rather than its quoted output
+fn str_leaf_node(text: &str, ctx: Context) -> TreeNode {
+    if ctx.multiline_leaves && text.contains('\n') {
+        return text_leaf_node(text, ctx);
+    }
+    match ctx
+        .max_leaf_width
+        .and_then(|max_width| text.char_indices().nth(max_width))
+    {
+        Some((end, _)) => TreeNode::leaf(format!("{:?}… ({} bytes)", &text[..end], text.len())),
+        None => TreeNode::leaf(format!("{:?}", text)),
+    }
+}
+
+// Cuts `text` after `max_width` characters, followed by `…` and the size of the whole
+// value in bytes
+fn truncate_leaf(text: &str, max_width: Option<usize>, bytes: usize) -> String {
+    match max_width.and_then(|max_width| text.char_indices().nth(max_width)) {
+        Some((end, _)) => format!("{}… ({} bytes)", &text[..end], bytes),
+        None => text.to_string(),
+    }
+}
+
+// The `…` node standing in for the children of `data` once `ctx.max_depth` is reached,
+// if they are elided
+pub fn elided_node<T: TreeDisplay + ?Sized>(data: &T, ctx: Context) -> Option<TreeNode> {
+    if !ctx.is_depth_exhausted() || data.is_leaf() || data.is_flatten_empty() {
+        return None;
+    }
+    let hidden = if ctx.count_elided {
+        count_descendants(data)
+    } else {
+        0
+    };
+    Some(elision_node(ctx, hidden))
+}
+
+// The `…` leaf, followed by the number of hidden nodes when `ctx.count_elided` is set
+pub fn elision_node(ctx: Context, hidden: usize) -> TreeNode {
+    if ctx.count_elided {
+        TreeNode::leaf(format!("… ({} hidden)", hidden))
+    } else {
+        TreeNode::leaf("…")
+    }
+}
+
+// The `… N more items` node in place of the items hidden by `ctx.max_items`
+fn more_items_node(hidden: usize) -> TreeNode {
+    let digits = hidden.to_string();
+    // Separators go before the digits a multiple of three away from the end
+    let offset = digits.len() % 3;
+    let mut count = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && i % 3 == offset {
+            count.push(',');
+        }
+        count.push(digit);
+    }
+    let noun = if hidden == 1 { "item" } else { "items" };
+    TreeNode {
+        label: Some(format!("… {} more {}", count, noun)),
+        kind: NodeKind::Marker,
+        ..TreeNode::default()
+    }
+}
+
+// Counts the nodes below `data`, shared values are counted once so that cyclic
+// graphs terminate
+pub fn count_descendants<T: TreeDisplay + ?Sized>(data: &T) -> usize {
+    count_hidden(|ctx| data.to_tree_node(ctx))
+}
+
+// Counts the nodes below the node built by `node_of` without depth or item limits, for
+// the `…` standing in for it
+pub fn count_hidden(node_of: impl FnOnce(Context) -> TreeNode) -> usize {
+    fn count(node: &TreeNode) -> usize {
+        node.children.iter().map(|child| 1 + count(child)).sum()
+    }
+
+    let shared_refs = SharedRefs::new();
+    count(&node_of(Context {
+        shared_refs: Some(&shared_refs),
+        ..Context::new()
+    }))
+}
+
+pub struct DataContainer<'a, T: TreeDisplay> {
+    pub data: T,
+    pub ctx: Context<'a>,
+    pub tctx: TransientContext,
+}
+
+impl<T: TreeDisplay> std::fmt::Display for DataContainer<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.data.tree_root_fmt(f, self.ctx, self.tctx)
+    }
+}
+
+macro_rules! tree_display_impl_primitive {
+    ($($t:ty),*) => {
+        $(
+            impl TreeDisplay for $t {
+                fn to_tree_node(&self, ctx: Context) -> TreeNode {
+                    text_leaf_node(&format!("{:?}", self), ctx)
+                }
+
+                fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    write!(f, " ({})", stringify!($t))
+                }
+
+                fn is_leaf(&self) -> bool {
+                    true
+                }
+            }
+        )*
+    };
+}
+
+tree_display_impl_primitive!(
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    f32,
+    f64,
+    bool,
+    char,
+    ()
+);
+
+impl TreeDisplay for String {
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        str_leaf_node(self, ctx)
+    }
+
+    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, " (String)")
+    }
+
+    fn is_leaf(&self) -> bool {
+        true
+    }
+}
+
+impl TreeDisplay for &str {
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        str_leaf_node(self, ctx)
+    }
+
+    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, " -> (str)")
+    }
+
+    fn is_leaf(&self) -> bool {
+        true
+    }
+}
+
+impl<T> TreeDisplay for &T
+where
+    T: TreeDisplay + ?Sized,
+{
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        (**self).to_tree_node(ctx)
+    }
+
+    fn tree_root_fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        ctx: Context,
+        tctx: TransientContext,
+    ) -> std::fmt::Result {
+        (**self).tree_root_fmt(f, ctx, tctx)
+    }
+
+    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, " ->")?;
+        T::type_name_fmt(self, f)
+    }
+
+    fn is_flatten_empty(&self) -> bool {
+        (**self).is_flatten_empty()
+    }
+
+    fn is_leaf(&self) -> bool {
+        (**self).is_leaf()
+    }
+}
+
+// A shared pointer is an `&id` node the first time it is seen and `*id (see above)` afterwards,
+// when `ctx.shared_refs` is set. Leaves and pointers without other owners are laid out as is
+fn shared_node<T: TreeDisplay + ?Sized>(
+    data: &T,
+    ptr: *const (),
+    strong_count: usize,
+    ctx: Context,
+) -> TreeNode {
+    let shared_refs = match ctx.shared_refs {
+        Some(shared_refs) if strong_count > 1 && !data.is_leaf() => shared_refs,
+        _ => return data.to_tree_node(ctx),
+    };
+    let (id, is_seen) = shared_refs.visit(ptr);
+    if is_seen {
+        return TreeNode {
+            layout: Layout(Spacing::Block),
+            ..TreeNode::leaf(format!("*{} (see above)", id))
+        };
+    }
+    TreeNode {
+        layout: Layout(Spacing::Gapless),
+        ..TreeNode::branch(vec![TreeNode {
+            label: Some(format!("&{}", id)),
+            kind: NodeKind::Entry,
+            ..data.to_tree_node(ctx)
+        }])
+    }
+}
+
+macro_rules! tree_display_impl_pointer {
+    ($t:ident, |$this:ident, $ctx:ident| $to_tree_node:expr) => {
+        impl<T> TreeDisplay for $t<T>
+        where
+            T: TreeDisplay + ?Sized,
+        {
+            fn to_tree_node(&self, $ctx: Context) -> TreeNode {
+                let $this = self;
+                $to_tree_node
+            }
+
+            fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, " ({}) ->", stringify!($t))?;
+                (**self).type_name_fmt(f)
+            }
+
+            fn is_flatten_empty(&self) -> bool {
+                (**self).is_flatten_empty()
+            }
+
+            fn is_leaf(&self) -> bool {
+                (**self).is_leaf()
+            }
+        }
+    };
 }
 
-impl Context<'_> {
-    pub fn new() -> Self {
-        Self::default()
+tree_display_impl_pointer!(Box, |this, ctx| (**this).to_tree_node(ctx));
+tree_display_impl_pointer!(Rc, |this, ctx| shared_node(
+    &**this,
+    Rc::as_ptr(this) as *const (),
+    Rc::strong_count(this),
+    ctx
+));
+tree_display_impl_pointer!(Arc, |this, ctx| shared_node(
+    &**this,
+    Arc::as_ptr(this) as *const (),
+    Arc::strong_count(this),
+    ctx
+));
+
+impl<B> TreeDisplay for Cow<'_, B>
+where
+    B: ToOwned + ?Sized,
+    for<'b> &'b B: TreeDisplay,
+{
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        (&**self).to_tree_node(ctx)
     }
-}
 
-#[derive(Debug, Clone, Copy, Default)]
-pub struct TransientContext {
-    pub is_flattened_and_last: Option<bool>,
-}
+    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cow::Borrowed(_) => write!(f, " (Cow::Borrowed)")?,
+            Cow::Owned(_) => write!(f, " (Cow::Owned)")?,
+        }
+        (&**self).type_name_fmt(f)
+    }
 
-impl TransientContext {
-    pub fn new() -> Self {
-        Self::default()
+    fn is_flatten_empty(&self) -> bool {
+        (&**self).is_flatten_empty()
+    }
+
+    fn is_leaf(&self) -> bool {
+        (&**self).is_leaf()
     }
 }
 
-pub trait TreeDisplay {
-    fn tree_fmt(
-        &self,
-        f: &mut std::fmt::Formatter<'_>,
-        ctx: Context, // Change to enum to show types, names or both? Also variants to rename any combination of the two
-        // flag to use edit friendly characters (better for tests)
-        // dense or sparse
-        tctx: TransientContext,
-    ) -> std::fmt::Result;
+impl<P> TreeDisplay for Pin<P>
+where
+    P: Deref,
+    P::Target: TreeDisplay,
+{
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        (**self).to_tree_node(ctx)
+    }
 
-    fn tree_print(&self, ctx: Context, tctx: TransientContext) -> String
-    where
-        Self: Sized,
-    {
-        DataContainer {
-            data: self,
-            ctx,
-            tctx,
-        }
-        .to_string()
+    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, " (Pin) ->")?;
+        (**self).type_name_fmt(f)
     }
 
-    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result;
+    fn is_flatten_empty(&self) -> bool {
+        (**self).is_flatten_empty()
+    }
+
+    fn is_leaf(&self) -> bool {
+        (**self).is_leaf()
+    }
 }
 
-pub struct DataContainer<'a, T: TreeDisplay> {
-    pub data: T,
-    pub ctx: Context<'a>,
-    pub tctx: TransientContext,
+impl<T> TreeDisplay for Cell<T>
+where
+    T: TreeDisplay + Copy,
+{
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        self.get().to_tree_node(ctx)
+    }
+
+    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, " (Cell) ->")?;
+        self.get().type_name_fmt(f)
+    }
+
+    fn is_flatten_empty(&self) -> bool {
+        self.get().is_flatten_empty()
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.get().is_leaf()
+    }
 }
 
-impl<T: TreeDisplay> std::fmt::Display for DataContainer<'_, T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.data.tree_fmt(f, self.ctx, self.tctx)
+// A RefCell that is mutably borrowed renders as `<borrowed>` instead of panicking
+impl<T> TreeDisplay for RefCell<T>
+where
+    T: TreeDisplay + ?Sized,
+{
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        match self.try_borrow() {
+            Ok(item) => item.to_tree_node(ctx),
+            Err(_) => TreeNode::leaf("<borrowed>"),
+        }
+    }
+
+    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.try_borrow() {
+            Ok(item) => {
+                write!(f, " (RefCell) ->")?;
+                item.type_name_fmt(f)
+            }
+            Err(_) => write!(f, " (RefCell)"),
+        }
+    }
+
+    fn is_flatten_empty(&self) -> bool {
+        match self.try_borrow() {
+            Ok(item) => item.is_flatten_empty(),
+            Err(_) => false,
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        match self.try_borrow() {
+            Ok(item) => item.is_leaf(),
+            Err(_) => true,
+        }
     }
 }
 
-macro_rules! tree_display_impl_primitive {
-    ($($t:ty),*) => {
+// Locks are only ever tried, a held lock renders as `<locked>` and a poisoned one as `<poisoned>`
+macro_rules! tree_display_impl_lock {
+    ($($t:ident => $try_lock:ident),*) => {
         $(
-            impl TreeDisplay for $t {
-                fn tree_fmt(&self, f: &mut std::fmt::Formatter<'_>, ctx: Context, _: TransientContext) -> std::fmt::Result {
-                    writeln!(f, "{}└─{:?}", ctx.indent, self)?;
-                    if let Some(sparcity) = ctx.sparcity {
-                        (0..sparcity.get()).try_for_each(|_| {
-                            writeln!(f, "{}", ctx.indent)
-                        })?;
+            impl<T> TreeDisplay for $t<T>
+            where
+                T: TreeDisplay + ?Sized,
+            {
+                fn to_tree_node(&self, ctx: Context) -> TreeNode {
+                    match self.$try_lock() {
+                        Ok(item) => item.to_tree_node(ctx),
+                        Err(TryLockError::WouldBlock) => TreeNode::leaf("<locked>"),
+                        Err(TryLockError::Poisoned(_)) => TreeNode::leaf("<poisoned>"),
                     }
-                    Ok(())
                 }
 
                 fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                    write!(f, " ({})", stringify!($t))
+                    match self.$try_lock() {
+                        Ok(item) => {
+                            write!(f, " ({}) ->", stringify!($t))?;
+                            item.type_name_fmt(f)
+                        }
+                        Err(_) => write!(f, " ({})", stringify!($t)),
+                    }
+                }
+
+                fn is_flatten_empty(&self) -> bool {
+                    match self.$try_lock() {
+                        Ok(item) => item.is_flatten_empty(),
+                        Err(_) => false,
+                    }
+                }
+
+                fn is_leaf(&self) -> bool {
+                    match self.$try_lock() {
+                        Ok(item) => item.is_leaf(),
+                        Err(_) => true,
+                    }
                 }
             }
         )*
     };
 }
 
-tree_display_impl_primitive!(
-    i8,
-    i16,
-    i32,
-    i64,
-    i128,
-    isize,
-    u8,
-    u16,
-    u32,
-    u64,
-    u128,
-    usize,
-    f32,
-    f64,
-    bool,
-    char,
-    String,
-    ()
-);
+tree_display_impl_lock!(Mutex => try_lock, RwLock => try_read);
 
-impl TreeDisplay for &str {
-    fn tree_fmt(
-        &self,
-        f: &mut std::fmt::Formatter<'_>,
-        ctx: Context,
-        _: TransientContext,
-    ) -> std::fmt::Result {
-        writeln!(f, "{}└─{:?}", ctx.indent, &self)?;
-        if let Some(sparcity) = ctx.sparcity {
-            (0..sparcity.get()).try_for_each(|_| writeln!(f, "{}", ctx.indent))?;
+// The node of a sequence, with items labelled `[i]` in iteration order.
+// Items hidden by `ctx.max_items` are replaced by a single `… N more items` node
+pub fn items_node<'a, T, I>(items: I, ctx: Context) -> TreeNode
+where
+    T: TreeDisplay + ?Sized + 'a,
+    I: ExactSizeIterator<Item = &'a T>,
+{
+    let hidden = ctx.hidden_items(items.len());
+    let mut children = Vec::new();
+    for (i, item) in items.enumerate() {
+        if hidden.contains(&i) {
+            if i == hidden.start {
+                children.push(more_items_node(hidden.len()));
+            }
+            continue;
+        }
+        children.push(TreeNode::child(
+            item,
+            format!("[{}]", i),
+            NodeKind::Item,
+            ctx,
+        ));
+    }
+    TreeNode::branch(children)
+}
+
+impl<T> TreeDisplay for [T]
+where
+    T: TreeDisplay,
+{
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        if let Some(node) = elided_node(self, ctx) {
+            return node;
         }
-        Ok(())
+        items_node(self.iter(), ctx)
     }
 
     fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, " -> (str)")
+        write!(f, " (Array)")
+    }
+
+    fn is_flatten_empty(&self) -> bool {
+        self.is_empty()
     }
 }
 
-impl<T> TreeDisplay for &T
+impl<T, const N: usize> TreeDisplay for [T; N]
 where
     T: TreeDisplay,
 {
-    fn tree_fmt<'a>(
-        &'a self,
-        f: &mut std::fmt::Formatter<'_>,
-        ctx: Context,
-        tctx: TransientContext,
-    ) -> std::fmt::Result {
-        (**self).tree_fmt(f, ctx, tctx)
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        if let Some(node) = elided_node(self, ctx) {
+            return node;
+        }
+        items_node(self.iter(), ctx)
     }
 
     fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, " ->")?;
-        T::type_name_fmt(self, f)
+        write!(f, " (Array[{}])", N)
+    }
+
+    fn is_flatten_empty(&self) -> bool {
+        N == 0
     }
 }
 
-impl<T> TreeDisplay for Box<T>
+impl<T> TreeDisplay for Vec<T>
 where
     T: TreeDisplay,
 {
-    fn tree_fmt<'a>(
-        &'a self,
-        f: &mut std::fmt::Formatter<'_>,
-        ctx: Context,
-        tctx: TransientContext,
-    ) -> std::fmt::Result {
-        (**self).tree_fmt(f, ctx, tctx)
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        self[..].to_tree_node(ctx)
     }
 
     fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, " (Box) ->")?;
-        T::type_name_fmt(self, f)
+        write!(f, " (Vec)")
+    }
+
+    fn is_flatten_empty(&self) -> bool {
+        self.is_empty()
     }
 }
 
-// TODO: show types (?)
-impl<T> TreeDisplay for [T]
+// The node of a map with entries in iteration order, entries with leaf keys are labelled
+// `[key]` by the value of the key node while other keys get an indexed node with `key`
+// and `value` children. Entries hidden by `ctx.max_items` are replaced by a single
+// `… N more items` node
+pub fn map_node<'a, K, V, I>(entries: I, ctx: Context) -> TreeNode
 where
-    T: TreeDisplay,
+    K: TreeDisplay + 'a,
+    V: TreeDisplay + 'a,
+    I: ExactSizeIterator<Item = (&'a K, &'a V)>,
 {
-    fn tree_fmt<'a>(
-        &'a self,
-        f: &mut std::fmt::Formatter<'_>,
-        ctx: Context,
-        _: TransientContext,
-    ) -> std::fmt::Result {
-        if let (Some(sparcity), false) = (ctx.sparcity, self.is_empty()) {
-            (0..sparcity.get()).try_for_each(|_| writeln!(f, "{}|", ctx.indent))?;
-        }
-        let mut new_indent = ctx.indent.to_string();
-        new_indent.push_str("|  ");
-        for (i, item) in self.iter().enumerate() {
-            if i < self.len() - 1 {
-                write!(f, "{}├─[{}]", ctx.indent, i)?;
-            } else {
-                write!(f, "{}└─[{}]", ctx.indent, i)?;
-                new_indent = ctx.indent.to_string();
-                new_indent.push_str("   ");
+    let hidden = ctx.hidden_items(entries.len());
+    let mut children = Vec::new();
+    for (i, (key, value)) in entries.enumerate() {
+        if hidden.contains(&i) {
+            if i == hidden.start {
+                children.push(more_items_node(hidden.len()));
             }
-            if ctx.show_types {
-                item.type_name_fmt(f)?;
-            }
-            writeln!(f)?;
-            item.tree_fmt(
-                f,
-                Context {
-                    indent: &new_indent,
-                    ..ctx
-                },
-                TransientContext::new(),
-            )?;
+            continue;
         }
-        if let (Some(sparcity), true) = (ctx.sparcity, self.is_empty()) {
-            (0..sparcity.get()).try_for_each(|_| writeln!(f, "{}", ctx.indent))?;
+        if key.is_leaf() {
+            let key = key.to_tree_node(ctx).value.unwrap_or_default();
+            children.push(TreeNode::child(
+                value,
+                format!("[{}]", key),
+                NodeKind::Entry,
+                ctx,
+            ));
+            continue;
         }
-        Ok(())
+        let ctx = Context {
+            depth: ctx.depth + 1,
+            ..ctx
+        };
+        let entry = elided_node(&(key, value), ctx).unwrap_or_else(|| {
+            TreeNode::branch(vec![
+                TreeNode::child(key, "key", NodeKind::Field, ctx),
+                TreeNode::child(value, "value", NodeKind::Field, ctx),
+            ])
+        });
+        children.push(TreeNode {
+            label: Some(format!("[{}]", i)),
+            kind: NodeKind::Entry,
+            ..entry
+        });
+    }
+    TreeNode::branch(children)
+}
+
+// Hash-based collections are shown in an order that does not depend on the hasher. Items
+// with leaf keys come after the others and are sorted by the value of their key node,
+// numerically when both are numbers. Remaining ties are broken by the `Debug` output of
+// the keys, which is all that orders keys that are not leaves
+fn sorted_by_key<E, T>(entries: impl Iterator<Item = E>, key_of: impl Fn(&E) -> &T) -> Vec<E>
+where
+    T: TreeDisplay + std::fmt::Debug + ?Sized,
+{
+    let mut entries = entries
+        .map(|entry| {
+            let key = key_of(&entry);
+            let label = if key.is_leaf() {
+                key.to_tree_node(Context::new()).value
+            } else {
+                None
+            };
+            (label, format!("{:?}", key), entry)
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by(|(a, a_debug, _), (b, b_debug, _)| {
+        let by_label = match (a, b) {
+            (Some(a), Some(b)) => match (number(a), number(b)) {
+                (Some(x), Some(y)) => x.total_cmp(&y).then_with(|| a.cmp(b)),
+                _ => a.cmp(b),
+            },
+            (a, b) => a.cmp(b),
+        };
+        by_label.then_with(|| a_debug.cmp(b_debug))
+    });
+    entries.into_iter().map(|(_, _, entry)| entry).collect()
+}
+
+// The value of a label made of digits, signs, a decimal point and an exponent
+fn number(label: &str) -> Option<f64> {
+    let is_numeric = label
+        .bytes()
+        .all(|b| b.is_ascii_digit() || matches!(b, b'-' | b'+' | b'.' | b'e' | b'E'));
+    if is_numeric {
+        label.parse().ok()
+    } else {
+        None
+    }
+}
+
+impl<K, V, S> TreeDisplay for HashMap<K, V, S>
+where
+    K: TreeDisplay + std::fmt::Debug,
+    V: TreeDisplay,
+{
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        if let Some(node) = elided_node(self, ctx) {
+            return node;
+        }
+        let entries = sorted_by_key(self.iter(), |&(key, _)| key);
+        map_node(entries.into_iter(), ctx)
     }
 
     fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, " (Array)")
+        write!(f, " (HashMap)")
+    }
+
+    fn is_flatten_empty(&self) -> bool {
+        self.is_empty()
     }
 }
 
-impl<T> TreeDisplay for Vec<T>
+impl<K, V> TreeDisplay for BTreeMap<K, V>
 where
-    T: TreeDisplay,
+    K: TreeDisplay,
+    V: TreeDisplay,
 {
-    fn tree_fmt<'a>(
-        &'a self,
-        f: &mut std::fmt::Formatter<'_>,
-        ctx: Context,
-        tctx: TransientContext,
-    ) -> std::fmt::Result {
-        self[..].tree_fmt(f, ctx, tctx)
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        if let Some(node) = elided_node(self, ctx) {
+            return node;
+        }
+        map_node(self.iter(), ctx)
     }
 
     fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, " (Vec)")
+        write!(f, " (BTreeMap)")
+    }
+
+    fn is_flatten_empty(&self) -> bool {
+        self.is_empty()
+    }
+}
+
+macro_rules! tree_display_impl_sequence {
+    ($($t:ident),*) => {
+        $(
+            impl<T> TreeDisplay for $t<T>
+            where
+                T: TreeDisplay,
+            {
+                fn to_tree_node(&self, ctx: Context) -> TreeNode {
+                    if let Some(node) = elided_node(self, ctx) {
+                        return node;
+                    }
+                    items_node(self.iter(), ctx)
+                }
+
+                fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    write!(f, " ({})", stringify!($t))
+                }
+
+                fn is_flatten_empty(&self) -> bool {
+                    self.is_empty()
+                }
+            }
+        )*
+    };
+}
+
+tree_display_impl_sequence!(VecDeque, LinkedList, BTreeSet);
+
+// HashSet items are sorted like HashMap keys, see `sorted_by_key`
+impl<T, S> TreeDisplay for HashSet<T, S>
+where
+    T: TreeDisplay + std::fmt::Debug,
+{
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        if let Some(node) = elided_node(self, ctx) {
+            return node;
+        }
+        let items = sorted_by_key(self.iter(), |&item| item);
+        items_node(items.into_iter(), ctx)
+    }
+
+    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, " (HashSet)")
+    }
+
+    fn is_flatten_empty(&self) -> bool {
+        self.is_empty()
+    }
+}
+
+// BinaryHeap items are shown in the order they would be popped
+impl<T> TreeDisplay for BinaryHeap<T>
+where
+    T: TreeDisplay + Ord,
+{
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        if let Some(node) = elided_node(self, ctx) {
+            return node;
+        }
+        let mut items = self.iter().collect::<Vec<_>>();
+        items.sort_by(|a, b| b.cmp(a));
+        items_node(items.into_iter(), ctx)
+    }
+
+    fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, " (BinaryHeap)")
+    }
+
+    fn is_flatten_empty(&self) -> bool {
+        self.is_empty()
     }
 }
 
-// TODO: Do maps, sets, vectors, arrays etc.
 // TODO: Do for references too and test
 // TODO: Indication for references/pointers?
 // TODO: Serde based version too (?)
@@ -242,37 +1762,33 @@ impl<T> TreeDisplay for Option<T>
 where
     T: TreeDisplay,
 {
-    fn tree_fmt<'a>(
-        &'a self,
-        f: &mut std::fmt::Formatter<'_>,
-        ctx: Context,
-        _: TransientContext,
-    ) -> std::fmt::Result {
-        let new_indent = ctx.indent.to_string();
-        if let Some(item) = self {
-            item.tree_fmt(
-                f,
-                Context {
-                    indent: &new_indent,
-                    ..ctx
-                },
-                TransientContext::new(),
-            )?;
-        } else {
-            if let Some(sparcity) = ctx.sparcity {
-                (0..sparcity.get()).try_for_each(|_| writeln!(f, "{}|", new_indent))?;
-            }
-            write!(f, "{}└─None", new_indent)?;
-            if let Some(sparcity) = ctx.sparcity {
-                (0..sparcity.get()).try_for_each(|_| writeln!(f, "{}", new_indent))?;
-            }
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        match self {
+            Some(item) => item.to_tree_node(ctx),
+            None => TreeNode {
+                layout: Layout(Spacing::Gapped),
+                ..TreeNode::leaf("None")
+            },
         }
-        Ok(())
     }
 
     fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, " (Option)")
     }
+
+    fn is_flatten_empty(&self) -> bool {
+        match self {
+            Some(item) => item.is_flatten_empty(),
+            None => true,
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        match self {
+            Some(item) => item.is_leaf(),
+            None => true,
+        }
+    }
 }
 
 impl<T, E> TreeDisplay for Result<T, E>
@@ -280,42 +1796,21 @@ where
     T: TreeDisplay,
     E: TreeDisplay,
 {
-    fn tree_fmt<'a>(
-        &'a self,
-        f: &mut std::fmt::Formatter<'_>,
-        ctx: Context,
-        _: TransientContext,
-    ) -> std::fmt::Result {
-        let mut new_indent = ctx.indent.to_string();
-        new_indent.push_str("   ");
-        if let Some(sparcity) = ctx.sparcity {
-            (0..sparcity.get()).try_for_each(|_| writeln!(f, "{}|", new_indent))?;
-        }
-        write!(f, "{}└─", new_indent)?;
-        if let Ok(item) = self {
-            writeln!(f, "Ok")?;
-            new_indent.push_str("   ");
-            item.tree_fmt(
-                f,
-                Context {
-                    indent: &new_indent,
-                    ..ctx
-                },
-                TransientContext::new(),
-            )?;
-        } else if let Err(item) = self {
-            writeln!(f, "Err")?;
-            new_indent.push_str("   ");
-            item.tree_fmt(
-                f,
-                Context {
-                    indent: &new_indent,
-                    ..ctx
-                },
-                TransientContext::new(),
-            )?;
+    fn to_tree_node(&self, ctx: Context) -> TreeNode {
+        if let Some(node) = elided_node(self, ctx) {
+            return node;
+        }
+        let entry = match self {
+            Ok(item) => TreeNode::child(item, "Ok", NodeKind::Entry, ctx),
+            Err(item) => TreeNode::child(item, "Err", NodeKind::Entry, ctx),
+        };
+        TreeNode {
+            layout: Layout(Spacing::Indented),
+            ..TreeNode::branch(vec![TreeNode {
+                type_name: None,
+                ..entry
+            }])
         }
-        Ok(())
     }
 
     fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -329,33 +1824,19 @@ macro_rules! tree_display_impl_tuple_primitive {
         impl<T, $($typ,)*> TreeDisplay for (T, $($typ,)* ) where
             T: TreeDisplay,
             $( $typ: TreeDisplay,)* {
-            fn tree_fmt(&self, f: &mut std::fmt::Formatter<'_>, ctx: Context, _: TransientContext) -> std::fmt::Result {
+            fn to_tree_node(&self, ctx: Context) -> TreeNode {
+                if let Some(node) = elided_node(self, ctx) {
+                    return node;
+                }
                 let (t, $($typ,)*) = self;
-                #[allow(unused_mut)]
-                let mut i = 0;
-                let mut new_indent = ctx.indent.to_string();
-                new_indent.push_str("|  ");
-                if let Some(sparcity) = ctx.sparcity {
-                    (0..sparcity.get()).try_for_each(|_| {
-                        writeln!(f, "{}|", ctx.indent)
-                    })?;
+                let mut children = vec![
+                    $(TreeNode::child($typ, String::new(), NodeKind::Field, ctx),)*
+                    TreeNode::child(t, String::new(), NodeKind::Field, ctx),
+                ];
+                for (i, child) in children.iter_mut().enumerate() {
+                    child.label = Some(i.to_string());
                 }
-                $(
-                    write!(f, "{}├──{}", ctx.indent, i)?;
-                    if ctx.show_types {
-                        $typ::type_name_fmt(&$typ, f)?;
-                    };
-                    writeln!(f)?;
-                    $typ.tree_fmt(f, Context { indent: &new_indent, ..ctx }, TransientContext::new())?;
-                    i += 1;
-                )*
-                write!(f, "{}└──{}", ctx.indent, i)?;
-                if ctx.show_types {
-                    t.type_name_fmt(f)?;
-                };
-                writeln!(f)?;
-                t.tree_fmt(f, Context {indent: &format!("   {}", ctx.indent), ..ctx}, TransientContext::new())?;
-                Ok(())
+                TreeNode::branch(children)
             }
 
             fn type_name_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {